@@ -1,17 +1,37 @@
-use std::sync::mpsc::Sender;
+use std::sync::mpsc::{Sender, Receiver, TryRecvError};
 use std::sync::mpsc;
-use msg::{CoreMessage, CoreFlag};
-use dbus::{Connection, BusType, NameFlag};
-use dbus::tree::Factory;
+use std::borrow::Cow;
+use msg::{CoreMessage, CoreFlag, ApiMessage, LidAction, InhibitKind, Inhibition, Status};
+use history;
+use power::SleepKind;
+use dbus::{Connection, ConnectionItem, BusType, NameFlag, Message, MessageItem};
+use dbus::tree::{Factory, MethodErr};
 
-pub fn actor_api(core: Sender<CoreMessage>) {
+const PATH: &'static str = "/de/kilobyte22/lockd";
+const INTERFACE: &'static str = "de.kilobyte22.lockd.Control";
+
+pub fn actor_api(core: Sender<CoreMessage>, inbox: Receiver<ApiMessage>) {
     let c = Connection::get_private(BusType::Session).unwrap();
     c.register_name("de.kilobyte22.lockd", NameFlag::ReplaceExisting as u32).unwrap();
     let f = Factory::new_fn();
 
-    let tree = f.tree().add(f.object_path("/de/kilobyte22/lockd")
+    let tree = f.tree().add(f.object_path(PATH)
         .introspectable().add(
-            f.interface("de.kilobyte22.lockd.Control").add_m(
+            f.interface(INTERFACE).add_s(
+                f.signal("Locked").sarg::<&str, _>("reason")
+            ).add_s(
+                f.signal("Unlocked").sarg::<&str, _>("reason")
+            ).add_s(
+                f.signal("AutoLockChanged").sarg::<bool, _>("enabled")
+            ).add_s(
+                f.signal("LidActionChanged").sarg::<&str, _>("action")
+            ).add_s(
+                f.signal("Suspending")
+            ).add_s(
+                f.signal("Resumed").sarg::<u64, _>("slept")
+            ).add_s(
+                f.signal("ConfigReloaded")
+            ).add_m(
                 f.method("Lock", |m, _, _| {
                     core.send(CoreMessage::Lock).unwrap();
                     Ok(vec![m.method_return()])
@@ -22,16 +42,53 @@ pub fn actor_api(core: Sender<CoreMessage>) {
                     Ok(vec![m.method_return()])
                 })
             ).add_m(
+                // Kept for older clients, true means suspend and false ignore, other lid actions read as false
                 f.method("SetSuspendOnLid", |m, _, _| {
-                    core.send(CoreMessage::SuspendOnLid(m.get1().unwrap())).unwrap();
+                    let value = try!(m.get1().ok_or_else(MethodErr::no_arg));
+                    core.send(CoreMessage::SetLidAction(suspend_or_ignore(value))).unwrap();
                     Ok(vec![m.method_return()])
                 }).inarg::<bool, _>("value")
+            ).add_m(
+                f.method("SetSuspendOnLidFor", |m, _, _| {
+                    let (value, secs) = match m.get2() {
+                        (Some(value), Some(secs)) => (value, secs),
+                        _ => return Err(MethodErr::no_arg())
+                    };
+                    core.send(CoreMessage::SetLidActionFor(suspend_or_ignore(value), secs)).unwrap();
+                    Ok(vec![m.method_return()])
+                }).inarg::<bool, _>("value").inarg::<u64, _>("seconds")
+            ).add_m(
+                f.method("SetLidAction", |m, _, _| {
+                    let name: Option<&str> = m.get1();
+                    let action = try!(lid_action(name));
+                    core.send(CoreMessage::SetLidAction(action)).unwrap();
+                    Ok(vec![m.method_return()])
+                }).inarg::<&str, _>("action")
+            ).add_m(
+                f.method("SetLidActionFor", |m, _, _| {
+                    let (name, secs): (Option<&str>, Option<u64>) = m.get2();
+                    let action = try!(lid_action(name));
+                    let secs = try!(secs.ok_or_else(MethodErr::no_arg));
+                    core.send(CoreMessage::SetLidActionFor(action, secs)).unwrap();
+                    Ok(vec![m.method_return()])
+                }).inarg::<&str, _>("action").inarg::<u64, _>("seconds")
+            ).add_m(
+                f.method("GetLidAction", |m, _, _| {
+                    let (tx, rx) = mpsc::channel::<LidAction>();
+                    core.send(CoreMessage::QueryLidAction(tx)).unwrap();
+                    Ok(vec![m.method_return().append1(rx.recv().unwrap().name())])
+                }).outarg::<&str, _>("action")
             ).add_m(
                 f.method("GetSuspendOnLid", |m, _, _| {
                     let (tx, rx) = mpsc::channel::<bool>();
                     core.send(CoreMessage::QueryFlag(CoreFlag::SuspendOnLid, tx)).unwrap();
                     Ok(vec![m.method_return().append1(rx.recv().unwrap())])
                 }).outarg::<bool, _>("value")
+            ).add_m(
+                // Handled in the loop below, as the reply has to wait for the lock screen and logind
+                f.method("Sleep", |_, _, _| {
+                    Ok(vec![])
+                }).inarg::<&str, _>("what")
             ).add_m(
                 f.method("Exit", |m, _, _| {
                     core.send(CoreMessage::Exit).unwrap();
@@ -42,6 +99,17 @@ pub fn actor_api(core: Sender<CoreMessage>) {
                     core.send(CoreMessage::ReloadConfig).unwrap();
                     Ok(vec![m.method_return()])
                 })
+            ).add_m(
+                // From idle watchers, cancels a pending autolock
+                f.method("Activity", |m, _, _| {
+                    core.send(CoreMessage::Activity).unwrap();
+                    Ok(vec![m.method_return()])
+                })
+            ).add_m(
+                f.method("Rescan", |m, _, _| {
+                    core.send(CoreMessage::Rescan).unwrap();
+                    Ok(vec![m.method_return()])
+                })
             ).add_m(
                 f.method("AutoLock", |m, _, _| {
                     core.send(CoreMessage::AutoLock).unwrap();
@@ -49,19 +117,196 @@ pub fn actor_api(core: Sender<CoreMessage>) {
                 })
             ).add_m(
                 f.method("SetAutoLock", |m, _, _| {
-                    core.send(CoreMessage::SetAutoLock(m.get1().unwrap())).unwrap();
+                    let value = try!(m.get1().ok_or_else(MethodErr::no_arg));
+                    core.send(CoreMessage::SetAutoLock(value)).unwrap();
                     Ok(vec![m.method_return()])
                 }).inarg::<bool, _>("value")
+            ).add_m(
+                f.method("SetAutoLockFor", |m, _, _| {
+                    let (value, secs) = match m.get2() {
+                        (Some(value), Some(secs)) => (value, secs),
+                        _ => return Err(MethodErr::no_arg())
+                    };
+                    core.send(CoreMessage::SetAutoLockFor(value, secs)).unwrap();
+                    Ok(vec![m.method_return()])
+                }).inarg::<bool, _>("value").inarg::<u64, _>("seconds")
             ).add_m(
                 f.method("GetAutoLock", |m, _, _| {
                     let (tx, rx) = mpsc::channel::<bool>();
                     core.send(CoreMessage::QueryFlag(CoreFlag::AutoLock, tx)).unwrap();
                     Ok(vec![m.method_return().append1(rx.recv().unwrap())])
                 }).outarg::<bool, _>("value")
+            ).add_m(
+                f.method("Inhibit", |m, _, _| {
+                    let (what, who, why): (Option<&str>, Option<&str>, Option<&str>) = m.get3();
+                    let kind = match what.and_then(InhibitKind::from_name) {
+                        Some(k) => k,
+                        None => return Err(MethodErr::invalid_arg(&what))
+                    };
+                    let (tx, rx) = mpsc::channel::<u32>();
+                    core.send(CoreMessage::Inhibit(Inhibition {
+                        cookie: 0,
+                        kind: kind,
+                        who: who.unwrap_or("").to_string(),
+                        why: why.unwrap_or("").to_string(),
+                        owner: try!(sender(m))
+                    }, tx)).unwrap();
+                    Ok(vec![m.method_return().append1(rx.recv().unwrap())])
+                }).inarg::<&str, _>("what").inarg::<&str, _>("who").inarg::<&str, _>("why").outarg::<u32, _>("cookie")
+            ).add_m(
+                f.method("Release", |m, _, _| {
+                    let cookie: u32 = match m.get1() {
+                        Some(c) => c,
+                        None => return Err(MethodErr::no_arg())
+                    };
+                    let (tx, rx) = mpsc::channel::<bool>();
+                    core.send(CoreMessage::Release(try!(sender(m)), cookie, tx)).unwrap();
+                    if rx.recv().unwrap() {
+                        Ok(vec![m.method_return()])
+                    } else {
+                        Err(MethodErr::invalid_arg(&cookie))
+                    }
+                }).inarg::<u32, _>("cookie")
+            ).add_m(
+                f.method("ListInhibitions", |m, _, _| {
+                    let (tx, rx) = mpsc::channel::<Vec<Inhibition>>();
+                    core.send(CoreMessage::ListInhibitions(tx)).unwrap();
+                    let inhibitions = rx.recv().unwrap().into_iter().map(|i| MessageItem::Struct(vec![
+                        i.cookie.into(),
+                        i.kind.name().into(),
+                        i.who.into(),
+                        i.why.into(),
+                        i.owner.into()
+                    ])).collect();
+                    Ok(vec![m.method_return().append(MessageItem::Array(inhibitions, Cow::Borrowed("(ussss)")))])
+                }).out_arg(("inhibitions", "a(ussss)"))
+            ).add_m(
+                f.method("GetStatus", |m, _, _| {
+                    let (tx, rx) = mpsc::channel::<Status>();
+                    core.send(CoreMessage::QueryStatus(tx)).unwrap();
+                    Ok(vec![m.method_return().append(status_dict(rx.recv().unwrap()))])
+                }).out_arg(("status", "a{sv}"))
+            ).add_m(
+                f.method("GetHistory", |m, _, _| {
+                    let (tx, rx) = mpsc::channel::<Vec<history::Entry>>();
+                    core.send(CoreMessage::QueryHistory(m.get1().unwrap_or(0), tx)).unwrap();
+                    let entries = rx.recv().unwrap().into_iter().map(|e| MessageItem::Struct(vec![
+                        e.time.into(),
+                        e.event.name().into(),
+                        e.reason.into(),
+                        e.duration.into()
+                    ])).collect();
+                    Ok(vec![m.method_return().append(MessageItem::Array(entries, Cow::Borrowed("(tsst)")))])
+                }).inarg::<u64, _>("since").out_arg(("history", "a(tsst)"))
             )
         )
     );
 
     tree.set_registered(&c, true).unwrap();
-    for _ in tree.run(&c, c.iter(1000)) {}
+    // Inhibitions die with the client that took them
+    c.add_match("type='signal',sender='org.freedesktop.DBus',interface='org.freedesktop.DBus',member='NameOwnerChanged',arg2=''").unwrap();
+    // Sleep calls waiting for the outcome, so other clients are not kept waiting meanwhile
+    let mut sleeping: Vec<(Message, Receiver<Result<(), String>>)> = Vec::new();
+    // Wake up regularly to emit signals core asked for
+    for item in c.iter(100) {
+        match item {
+            ConnectionItem::MethodCall(msg) => {
+                if msg.interface().map_or(false, |i| &*i == INTERFACE) && msg.member().map_or(false, |m| &*m == "Sleep") {
+                    let kind = msg.get1::<&str>().and_then(SleepKind::from_name);
+                    match kind {
+                        Some(kind) => {
+                            let (tx, rx) = mpsc::channel::<Result<(), String>>();
+                            core.send(CoreMessage::Sleep(kind, tx)).unwrap();
+                            sleeping.push((msg, rx));
+                        },
+                        None => {
+                            let _ = c.send(Message::new_error(&msg, "org.freedesktop.DBus.Error.InvalidArgs", "Expected suspend, hibernate or hybrid-sleep").unwrap());
+                        }
+                    }
+                } else if let Some(replies) = tree.handle(&msg) {
+                    for reply in replies {
+                        let _ = c.send(reply);
+                    }
+                }
+            },
+            ConnectionItem::Signal(msg) => {
+                if let (Some(name), Some(_), Some("")) = msg.get3::<&str, &str, &str>() {
+                    core.send(CoreMessage::NameLost(name.to_string())).unwrap();
+                }
+            },
+            _ => {}
+        }
+        // Replies once logind took the request, which is after the lock screen came up
+        sleeping.retain(|&(ref msg, ref rx)| {
+            let reply = match rx.try_recv() {
+                Ok(Ok(())) => msg.method_return(),
+                Ok(Err(e)) => Message::new_error(msg, "org.freedesktop.DBus.Error.Failed", &e).unwrap(),
+                Err(TryRecvError::Empty) => return true,
+                Err(TryRecvError::Disconnected) => Message::new_error(msg, "org.freedesktop.DBus.Error.Failed", "lockd gave up").unwrap()
+            };
+            let _ = c.send(reply);
+            false
+        });
+        while let Ok(message) = inbox.try_recv() {
+            c.send(signal(message)).unwrap();
+        }
+    }
+}
+
+fn sender(m: &Message) -> Result<String, MethodErr> {
+    match m.sender() {
+        Some(s) => Ok(s.to_string()),
+        None => Err(MethodErr::failed(&"Unknown sender"))
+    }
+}
+
+fn suspend_or_ignore(value: bool) -> Option<LidAction> {
+    Some(if value { LidAction::Suspend } else { LidAction::Ignore })
+}
+
+/// "auto" gives None, going back to the policy from the configuration
+fn lid_action(name: Option<&str>) -> Result<Option<LidAction>, MethodErr> {
+    match name {
+        Some("auto") => Ok(None),
+        _ => match name.and_then(LidAction::from_name) {
+            Some(action) => Ok(Some(action)),
+            None => Err(MethodErr::invalid_arg(&name))
+        }
+    }
+}
+
+fn signal(message: ApiMessage) -> Message {
+    let new = |name: &str| Message::new_signal(PATH, INTERFACE, name).unwrap();
+    match message {
+        ApiMessage::Locked(reason) => new("Locked").append1(reason.name()),
+        ApiMessage::Unlocked(reason) => new("Unlocked").append1(reason),
+        ApiMessage::AutoLockChanged(value) => new("AutoLockChanged").append1(value),
+        ApiMessage::LidActionChanged(value) => new("LidActionChanged").append1(value.name()),
+        ApiMessage::Suspending => new("Suspending"),
+        ApiMessage::Resumed(slept) => new("Resumed").append1(slept),
+        ApiMessage::ConfigReloaded => new("ConfigReloaded")
+    }
+}
+
+fn status_dict(s: Status) -> MessageItem {
+    let inhibitors = s.inhibitors.into_iter().map(|i| i.into()).collect();
+    let inhibitions = s.inhibitions.into_iter().map(|i| i.into()).collect();
+    let entries: Vec<(&str, MessageItem)> = vec![
+        ("locked", s.locked.into()),
+        ("locking", s.locking.into()),
+        ("locked_since", s.locked_since.into()),
+        ("lock_reason", s.lock_reason.map_or("", |r| r.name()).into()),
+        ("autolock", s.autolock.into()),
+        ("autolock_revert_in", s.autolock_revert_in.into()),
+        ("lidaction", s.lid_action.name().into()),
+        ("lidaction_revert_in", s.lidaction_revert_in.into()),
+        ("lidaction_auto", s.lid_action_auto.into()),
+        ("docked", s.docked.into()),
+        ("on_battery", s.on_battery.into()),
+        ("inhibitors", MessageItem::Array(inhibitors, Cow::Borrowed("s"))),
+        ("inhibitions", MessageItem::Array(inhibitions, Cow::Borrowed("s"))),
+        ("locker_pid", s.locker_pid.unwrap_or(0).into()),
+        ("config", s.config_path.into())
+    ];
+    MessageItem::from_dict::<(), _>(entries.into_iter().map(|(k, v)| Ok((k.to_string(), v)))).unwrap()
 }
@@ -0,0 +1,136 @@
+// Reads the backlight from sysfs and sets it through logind, which lets the session owner do so without root.
+
+use dbus::{Connection, Message};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use msg::Backlight;
+use logind;
+
+/// Name and brightness of the backlight device, the configured one or the first one found
+pub fn current(config: &Backlight) -> Option<(String, u32)> {
+    let name = match config.device {
+        Some(ref d) => d.clone(),
+        None => {
+            let mut names: Vec<String> = match fs::read_dir(&config.sysfs) {
+                Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.file_name().to_string_lossy().into_owned()).collect(),
+                Err(_) => Vec::new()
+            };
+            names.sort();
+            match names.into_iter().next() {
+                Some(n) => n,
+                None => return None
+            }
+        }
+    };
+    let mut s = String::new();
+    if File::open(Path::new(&config.sysfs).join(&name).join("brightness")).and_then(|mut f| f.read_to_string(&mut s)).is_err() {
+        return None;
+    }
+    s.trim().parse().ok().map(|b| (name, b))
+}
+
+/// Sets the backlight through our logind session. The connection is opened on first use and kept
+/// until a call fails.
+pub struct Control {
+    config: Backlight,
+    // Connection and the path of our session
+    session: Option<(Connection, String)>
+}
+
+impl Control {
+    pub fn new(config: Backlight) -> Control {
+        Control { config: config, session: None }
+    }
+
+    pub fn config(&self) -> &Backlight {
+        &self.config
+    }
+
+    /// Sets the brightness of the backlight device name
+    pub fn set(&mut self, name: &str, brightness: u32) -> Result<(), String> {
+        if self.session.is_none() {
+            let con = try!(Connection::get_private(self.config.bus).map_err(|e| format!("{:?}", e)));
+            let path = match logind::session_path(&con, &self.config.logind) {
+                Some(p) => p,
+                None => return Err(format!("could not find our logind session"))
+            };
+            self.session = Some((con, path));
+        }
+        let result = {
+            let (ref con, ref path) = *self.session.as_ref().unwrap();
+            let m = Message::new_method_call(&*self.config.logind, &**path, "org.freedesktop.login1.Session", "SetBrightness").unwrap()
+                .append3("backlight", name, brightness);
+            con.send_with_reply_and_block(m, 2000).map(|_| ())
+                .map_err(|e| format!("{}: {}", e.name().unwrap_or("unknown error"), e.message().unwrap_or("")))
+        };
+        // logind may have restarted, look for it again next time
+        if result.is_err() {
+            self.session = None;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dbus::BusType;
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::process;
+
+    use msg::Backlight;
+    use super::current;
+
+    // A sysfs like tree with two devices, removed again when the test is done
+    struct FakeSysfs(String);
+
+    impl FakeSysfs {
+        fn new(test: &str) -> FakeSysfs {
+            let dir = env::temp_dir().join(format!("lockd-backlight-{}-{}", test, process::id()));
+            for &(device, brightness) in &[("intel_backlight", "937\n"), ("acpi_video0", "15\n")] {
+                fs::create_dir_all(dir.join(device)).unwrap();
+                File::create(dir.join(device).join("brightness")).unwrap().write_all(brightness.as_bytes()).unwrap();
+            }
+            FakeSysfs(dir.to_string_lossy().into_owned())
+        }
+
+        fn config(&self, device: Option<&str>) -> Backlight {
+            Backlight {
+                sysfs: self.0.clone(),
+                device: device.map(|d| d.to_string()),
+                logind: format!("org.freedesktop.login1"),
+                bus: BusType::System
+            }
+        }
+    }
+
+    impl Drop for FakeSysfs {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn first_device() {
+        let sysfs = FakeSysfs::new("first");
+        assert_eq!(current(&sysfs.config(None)), Some((format!("acpi_video0"), 15)));
+    }
+
+    #[test]
+    fn configured_device() {
+        let sysfs = FakeSysfs::new("configured");
+        assert_eq!(current(&sysfs.config(Some("intel_backlight"))), Some((format!("intel_backlight"), 937)));
+        assert_eq!(current(&sysfs.config(Some("missing"))), None);
+    }
+
+    #[test]
+    fn no_devices() {
+        let sysfs = FakeSysfs::new("empty");
+        let mut config = sysfs.config(None);
+        config.sysfs = format!("{}/nothing", sysfs.0);
+        assert_eq!(current(&config), None);
+    }
+}
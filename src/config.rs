@@ -1,9 +1,17 @@
 use config_parser as cfg;
+use dbus::BusType;
+use std::env;
 use std::error::Error;
 use std::fmt;
 use std::fmt::Debug;
 use std::result::Result;
 use std::fs::File;
+use std::path::Path;
+use std::time::Duration;
+use libc;
+
+use msg::{LidAction, LidPolicy, Key, KeyAction, LockEnvironment, LockReason, LockCommand, LockerTable, AutoLockWarning, Backlight, BlankAction};
+use template::Template;
 
 pub const DEFAULT: &'static str = include_str!("../default.cfg");
 
@@ -14,10 +22,34 @@ pub enum DefaultValue {
 }
 
 pub struct Config {
-    lock_command: String,
-    lock_params: Vec<String>,
+    lock_command: LockCommand,
+    // Name and command from the lockers block
+    lockers: Vec<(String, LockCommand)>,
+    triggers: Vec<(LockReason, LockCommand)>,
+    unlock_signal: i32,
+    unlock_timeout: Duration,
+    environment: LockEnvironment,
     default_autolock: DefaultValue,
-    default_suspend_on_lid: DefaultValue
+    autolock_warning: AutoLockWarning,
+    grace_period: Duration,
+    backlight: Backlight,
+    // Zero never blanks
+    blank_after: Duration,
+    blank_action: BlankAction,
+    // None means remember
+    default_lid_action: Option<LidAction>,
+    // From the lidaction block, None falls back to the default
+    lid_battery: Option<LidAction>,
+    lid_ac: Option<LidAction>,
+    lid_docked: Option<LidAction>,
+    key_device: Option<String>,
+    key_actions: Vec<(Key, KeyAction)>,
+    // Commands with their parameters
+    pre_shutdown: Vec<(String, Vec<String>)>,
+    pre_shutdown_timeout: Duration,
+    stop_locker_on_shutdown: bool,
+    history_max_size: u64,
+    history_keep: u32
 }
 
 impl Config {
@@ -25,10 +57,40 @@ impl Config {
     pub fn parse(config: String) -> Result<Config, ConfigError> {
 
         let mut ret = Config {
-            lock_command: format!("i3lock"),
-            lock_params: vec![format!("-c"), format!("000000"), format!("--nofork")],
-            default_autolock: On,
-            default_suspend_on_lid: On
+            lock_command: LockCommand {
+                program: format!("i3lock"),
+                args: vec![Template::text("-c"), Template::text("000000"), Template::text("--nofork")],
+                ready_delay: Duration::from_millis(500),
+                unlock_signal: None
+            },
+            lockers: Vec::new(),
+            triggers: Vec::new(),
+            unlock_signal: libc::SIGTERM,
+            unlock_timeout: Duration::from_secs(5),
+            environment: LockEnvironment::default(),
+            default_autolock: DefaultValue::On,
+            autolock_warning: AutoLockWarning::default(),
+            grace_period: Duration::from_secs(0),
+            blank_after: Duration::from_secs(0),
+            blank_action: BlankAction::Command((format!("xset"), vec![format!("dpms"), format!("force"), format!("off")]), Some((format!("xset"), vec![format!("dpms"), format!("force"), format!("on")]))),
+            backlight: Backlight {
+                sysfs: format!("/sys/class/backlight"),
+                device: None,
+                logind: format!("org.freedesktop.login1"),
+                bus: BusType::System
+            },
+            default_lid_action: Some(LidAction::Suspend),
+            lid_battery: None,
+            lid_ac: None,
+            lid_docked: None,
+            key_device: None,
+            key_actions: Vec::new(),
+            pre_shutdown: Vec::new(),
+            // Leaves logind's default InhibitDelayMaxSec of 5 seconds some time to stop the lock screen
+            pre_shutdown_timeout: Duration::from_secs(3),
+            stop_locker_on_shutdown: false,
+            history_max_size: 1024 * 1024,
+            history_keep: 3
         };
 
         let c = match cfg::parse_string(config) {
@@ -37,15 +99,109 @@ impl Config {
         };
 
         match c.matching("lock_cmd").next() {
-            Some(cmd) => {
-                if cmd.len() < 1 {
-                    return Err(ConfigError::option("lock_cmd", "You have to specify a command and optionally parameters"));
+            Some(cmd) => ret.lock_command = try!(lock_command("lock_cmd", cmd)),
+            None => {}
+        }
+
+        match c.matching("lockers").next() {
+            Some(lockers) => {
+                for locker in lockers.inner() {
+                    let option = format!("lockers.{}", locker.name());
+                    if locker.name() == "default" {
+                        return Err(ConfigError::option(&option, "default is lock_cmd and cannot be redefined"));
+                    }
+                    ret.lockers.push((locker.name().to_string(), try!(lock_command(&option, locker))));
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("triggers").next() {
+            Some(triggers) => {
+                for trigger in triggers.inner() {
+                    let option = format!("triggers.{}", trigger.name());
+                    let reason = match LockReason::from_trigger(trigger.name()) {
+                        Some(r) => r,
+                        None => return Err(ConfigError::option(&option, "Expected manual, autolock, suspend, lid, key or session"))
+                    };
+                    let command = match trigger.get_opt(0) {
+                        Some("default") => ret.lock_command.clone(),
+                        Some(name) => match ret.lockers.iter().find(|&&(ref n, _)| n == name) {
+                            Some(&(_, ref command)) => command.clone(),
+                            None => return Err(ConfigError::option(&option, &format!("There is no locker named {} in the lockers block", name)))
+                        },
+                        None => return Err(ConfigError::option(&option, "Expected the name of a locker"))
+                    };
+                    ret.triggers.push((reason, command));
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("unlock_signal").next() {
+            Some(option) => {
+                ret.unlock_signal = match option.get_opt(0).and_then(signal) {
+                    Some(s) => s,
+                    None => return Err(ConfigError::option("unlock_signal", "Expected a signal name like TERM or USR1, or a number"))
                 }
-                ret.lock_command = cmd.get(0).to_string();
-                ret.lock_params = Vec::with_capacity(cmd.len() - 1);
-                for i in 1..cmd.len() {
-                    println!("Getting option {} of {}", i, cmd.len());
-                    ret.lock_params.push(cmd.get(i).to_string());
+            },
+            None => {}
+        }
+
+        match c.matching("unlock_timeout").next() {
+            Some(option) => {
+                ret.unlock_timeout = match option.get_opt(0).and_then(|s| s.parse().ok()) {
+                    Some(secs) => Duration::from_secs(secs),
+                    None => return Err(ConfigError::option("unlock_timeout", "Expected a number of seconds"))
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("environment").next() {
+            Some(environment) => {
+                match environment.matching("clear_env").next() {
+                    Some(clear_env) => {
+                        ret.environment.clear = match clear_env.get_opt(0) {
+                            Some("on") => true,
+                            Some("off") => false,
+                            _ => return Err(ConfigError::option("environment.clear_env", "Expected on or off"))
+                        }
+                    },
+                    None => {}
+                }
+                match environment.matching("import_systemd").next() {
+                    Some(import) => {
+                        ret.environment.import_systemd = Some((0..import.len()).map(|i| import.get(i).to_string()).collect());
+                    },
+                    None => {}
+                }
+                match environment.matching("env_file").next() {
+                    Some(env_file) => {
+                        ret.environment.env_file = match env_file.get_opt(0) {
+                            Some(path) => Some(path.to_string()),
+                            None => return Err(ConfigError::option("environment.env_file", "Expected the path of a file"))
+                        }
+                    },
+                    None => {}
+                }
+                for var in environment.matching("set") {
+                    if var.len() < 1 {
+                        return Err(ConfigError::option("environment.set", "You have to specify a variable and its value"));
+                    }
+                    let value: Vec<&str> = (1..var.len()).map(|i| var.get(i)).collect();
+                    ret.environment.vars.push((var.get(0).to_string(), value.join(" ")));
+                }
+                match environment.matching("working_dir").next() {
+                    Some(working_dir) => {
+                        ret.environment.working_dir = match working_dir.get_opt(0) {
+                            // Checked here, as the lock screen could not start otherwise
+                            Some(path) if Path::new(path).is_dir() => Some(path.to_string()),
+                            Some(path) => return Err(ConfigError::option("environment.working_dir", &format!("{} is not a directory", path))),
+                            None => return Err(ConfigError::option("environment.working_dir", "Expected a directory"))
+                        }
+                    },
+                    None => {}
                 }
             },
             None => {}
@@ -55,7 +211,7 @@ impl Config {
             Some(default) => {
                 match default.matching("autolock").next() {
                     Some(autolock) => {
-                        ret.autolock = match autolock.get_opt(0) {
+                        ret.default_autolock = match autolock.get_opt(0) {
                             Some("on") => DefaultValue::On,
                             Some("off") => DefaultValue::Off,
                             Some("remember") => DefaultValue::Remember,
@@ -67,22 +223,423 @@ impl Config {
 
                 match default.matching("lidaction").next() {
                     Some(lidaction) => {
-                        ret.default_suspend_on_lid = match lidaction.get_opt(0) {
-                            Some("suspend") => DefaultValue::On,
-                            Some("ignore") => DefaultValue::Off,
-                            Some("remember") => DefaultValue::Remember
+                        ret.default_lid_action = match lidaction.get_opt(0) {
+                            Some("remember") => None,
+                            Some(name) => Some(try!(lid_action("default.lidaction", name))),
+                            None => Some(LidAction::Suspend)
+                        }
+                    },
+                    None => {}
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("autolock_warning").next() {
+            Some(warning) => {
+                match warning.matching("duration").next() {
+                    Some(duration) => {
+                        ret.autolock_warning.duration = match duration.get_opt(0).and_then(|s| s.parse().ok()) {
+                            Some(secs) => Duration::from_secs(secs),
+                            None => return Err(ConfigError::option("autolock_warning.duration", "Expected a number of seconds"))
                         }
+                    },
+                    None => {}
+                }
+                match warning.matching("notify").next() {
+                    Some(notify) => {
+                        ret.autolock_warning.notify = match notify.get_opt(0) {
+                            Some("on") => true,
+                            Some("off") => false,
+                            _ => return Err(ConfigError::option("autolock_warning.notify", "Expected on or off"))
+                        }
+                    },
+                    None => {}
+                }
+                match warning.matching("dim").next() {
+                    Some(dim) => {
+                        ret.autolock_warning.dim = match dim.get_opt(0).and_then(|s| s.parse().ok()) {
+                            Some(percent) if percent <= 100 => Some(percent),
+                            _ => return Err(ConfigError::option("autolock_warning.dim", "Expected a percentage of the current brightness"))
+                        }
+                    },
+                    None => {}
+                }
+                match warning.matching("command").next() {
+                    Some(command) => ret.autolock_warning.command = Some(try!(plain_command("autolock_warning.command", command))),
+                    None => {}
+                }
+                match warning.matching("cancel_command").next() {
+                    Some(command) => ret.autolock_warning.cancel_command = Some(try!(plain_command("autolock_warning.cancel_command", command))),
+                    None => {}
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("backlight").next() {
+            Some(backlight) => {
+                match backlight.matching("sysfs").next() {
+                    Some(sysfs) => {
+                        ret.backlight.sysfs = match sysfs.get_opt(0) {
+                            Some(path) => path.to_string(),
+                            None => return Err(ConfigError::option("backlight.sysfs", "Expected a directory"))
+                        }
+                    },
+                    None => {}
+                }
+                match backlight.matching("device").next() {
+                    Some(device) => {
+                        ret.backlight.device = match device.get_opt(0) {
+                            Some(name) => Some(name.to_string()),
+                            None => return Err(ConfigError::option("backlight.device", "Expected the name of a backlight device"))
+                        }
+                    },
+                    None => {}
+                }
+                match backlight.matching("logind").next() {
+                    Some(logind) => {
+                        ret.backlight.logind = match logind.get_opt(0) {
+                            Some(name) => name.to_string(),
+                            None => return Err(ConfigError::option("backlight.logind", "Expected a D-Bus name"))
+                        }
+                    },
+                    None => {}
+                }
+                match backlight.matching("bus").next() {
+                    Some(bus) => {
+                        ret.backlight.bus = match bus.get_opt(0) {
+                            Some("system") => BusType::System,
+                            Some("session") => BusType::Session,
+                            _ => return Err(ConfigError::option("backlight.bus", "Expected system or session"))
+                        }
+                    },
+                    None => {}
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("blank_after").next() {
+            Some(option) => {
+                ret.blank_after = match option.get_opt(0).and_then(|s| s.parse().ok()) {
+                    Some(secs) => Duration::from_secs(secs),
+                    None => return Err(ConfigError::option("blank_after", "Expected a number of seconds"))
+                }
+            },
+            None => {}
+        }
+
+        match (c.matching("blank_off").next(), c.matching("blank_on").next()) {
+            (Some(off), Some(_)) if off.get_opt(0) == Some("backlight") =>
+                return Err(ConfigError::option("blank_on", "blank_off backlight turns the backlight back on by itself, leave out blank_on")),
+            (None, Some(_)) => return Err(ConfigError::option("blank_on", "Only used together with blank_off")),
+            _ => {}
+        }
+        match c.matching("blank_off").next() {
+            Some(off) if off.get_opt(0) == Some("backlight") => ret.blank_action = BlankAction::Backlight,
+            Some(off) => {
+                let off = try!(plain_command("blank_off", off));
+                let on = match c.matching("blank_on").next() {
+                    Some(on) => Some(try!(plain_command("blank_on", on))),
+                    None => None
+                };
+                ret.blank_action = BlankAction::Command(off, on);
+            },
+            None => {}
+        }
+
+        match c.matching("grace_period").next() {
+            Some(option) => {
+                ret.grace_period = match option.get_opt(0).and_then(|s| s.parse().ok()) {
+                    Some(secs) => Duration::from_secs(secs),
+                    None => return Err(ConfigError::option("grace_period", "Expected a number of seconds"))
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("lidaction").next() {
+            Some(lidaction) => {
+                match lidaction.matching("battery").next().and_then(|o| o.get_opt(0)) {
+                    Some(name) => ret.lid_battery = Some(try!(lid_action("lidaction.battery", name))),
+                    None => {}
+                }
+                match lidaction.matching("ac").next().and_then(|o| o.get_opt(0)) {
+                    Some(name) => ret.lid_ac = Some(try!(lid_action("lidaction.ac", name))),
+                    None => {}
+                }
+                match lidaction.matching("docked").next().and_then(|o| o.get_opt(0)) {
+                    Some(name) => ret.lid_docked = Some(try!(lid_action("lidaction.docked", name))),
+                    None => {}
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("keys").next() {
+            Some(keys) => {
+                match keys.matching("device").next() {
+                    Some(device) => {
+                        ret.key_device = match device.get_opt(0) {
+                            Some(path) => Some(path.to_string()),
+                            None => return Err(ConfigError::option("keys.device", "Expected the path of an input device"))
+                        }
+                    },
+                    None => {}
+                }
+                for &key in &[Key::Power, Key::Suspend, Key::Hibernate] {
+                    match keys.matching(key.name()).next() {
+                        Some(action) => {
+                            let option = format!("keys.{}", key.name());
+                            let action = match action.get_opt(0) {
+                                Some("lock") => KeyAction::Lock,
+                                Some("suspend") => KeyAction::Suspend,
+                                Some("hibernate") => KeyAction::Hibernate,
+                                Some("lock-then-suspend") => KeyAction::LockThenSuspend,
+                                Some("ignore") => KeyAction::Ignore,
+                                Some("command") if action.len() > 1 => {
+                                    let params = (2..action.len()).map(|i| action.get(i).to_string()).collect();
+                                    KeyAction::Command(action.get(1).to_string(), params)
+                                },
+                                Some("command") => return Err(ConfigError::option(&option, "You have to specify a command and optionally parameters")),
+                                _ => return Err(ConfigError::option(&option, "Expected lock, suspend, hibernate, lock-then-suspend, ignore or command"))
+                            };
+                            ret.key_actions.push((key, action));
+                        },
+                        None => {}
+                    }
+                }
+            },
+            None => {}
+        }
+
+        match c.matching("shutdown").next() {
+            Some(shutdown) => {
+                for hook in shutdown.matching("pre_shutdown") {
+                    if hook.len() < 1 {
+                        return Err(ConfigError::option("shutdown.pre_shutdown", "You have to specify a command and optionally parameters"));
                     }
+                    let params = (1..hook.len()).map(|i| hook.get(i).to_string()).collect();
+                    ret.pre_shutdown.push((hook.get(0).to_string(), params));
+                }
+                match shutdown.matching("timeout").next() {
+                    Some(timeout) => {
+                        ret.pre_shutdown_timeout = match timeout.get_opt(0).and_then(|s| s.parse().ok()) {
+                            Some(secs) => Duration::from_secs(secs),
+                            None => return Err(ConfigError::option("shutdown.timeout", "Expected a number of seconds"))
+                        }
+                    },
+                    None => {}
+                }
+                match shutdown.matching("stop_locker").next() {
+                    Some(stop_locker) => {
+                        ret.stop_locker_on_shutdown = match stop_locker.get_opt(0) {
+                            Some("on") => true,
+                            Some("off") => false,
+                            _ => return Err(ConfigError::option("shutdown.stop_locker", "Expected on or off"))
+                        }
+                    },
+                    None => {}
                 }
             },
             None => {}
         }
 
+        match c.matching("history").next() {
+            Some(history) => {
+                match history.matching("max_size").next() {
+                    Some(max_size) => {
+                        ret.history_max_size = match max_size.get_opt(0).and_then(|s| s.parse().ok()) {
+                            Some(n) => n,
+                            None => return Err(ConfigError::option("history.max_size", "Expected a size in bytes"))
+                        }
+                    },
+                    None => {}
+                }
+
+                match history.matching("keep").next() {
+                    Some(keep) => {
+                        ret.history_keep = match keep.get_opt(0).and_then(|s| s.parse().ok()) {
+                            Some(n) => n,
+                            None => return Err(ConfigError::option("history.keep", "Expected a number of files"))
+                        }
+                    },
+                    None => {}
+                }
+            },
+            None => {}
+        }
+
+        // A typo would otherwise only show up when the screen should lock. The environment block may set PATH.
+        let path = match ret.environment.vars.iter().find(|&&(ref name, _)| name == "PATH") {
+            Some(&(_, ref value)) => value.clone(),
+            None => env::var("PATH").unwrap_or(String::new())
+        };
+        if !program_exists(&ret.lock_command.program, &path) {
+            return Err(ConfigError::option("lock_cmd", &format!("{} is neither a file nor found in PATH", ret.lock_command.program)));
+        }
+        for &(ref name, ref command) in &ret.lockers {
+            if !program_exists(&command.program, &path) {
+                return Err(ConfigError::option(&format!("lockers.{}", name), &format!("{} is neither a file nor found in PATH", command.program)));
+            }
+        }
+
         Ok(ret)
     }
 
-    pub fn get_lock_command(&self) -> (&str, &[String]) {
-        (&self.lock_command, &self.lock_params)
+    /// Lock command for every lock reason
+    pub fn get_lockers(&self) -> LockerTable {
+        LockerTable {
+            default: self.lock_command.clone(),
+            triggers: self.triggers.clone()
+        }
+    }
+
+    /// Signal that asks the lock screen to exit and how long it gets before being killed
+    pub fn get_unlock_policy(&self) -> (i32, Duration) {
+        (self.unlock_signal, self.unlock_timeout)
+    }
+
+    /// How to set up the environment of the lock command
+    pub fn get_lock_environment(&self) -> &LockEnvironment {
+        &self.environment
+    }
+
+    /// Whether and how to warn before autolock locks the screen
+    pub fn get_autolock_warning(&self) -> &AutoLockWarning {
+        &self.autolock_warning
+    }
+
+    /// How long after autolock activity unlocks without a password
+    pub fn get_grace_period(&self) -> Duration {
+        self.grace_period
+    }
+
+    /// Where to find the backlight and whom to ask to change it
+    pub fn get_backlight(&self) -> &Backlight {
+        &self.backlight
+    }
+
+    /// How long after locking to turn the display off, and how
+    pub fn get_blank(&self) -> (Duration, &BlankAction) {
+        (self.blank_after, &self.blank_action)
+    }
+
+    /// What to do when the lid is closed, depending on power state
+    pub fn get_lid_policy(&self) -> LidPolicy {
+        let fallback = self.default_lid_action.unwrap_or(LidAction::Suspend);
+        LidPolicy {
+            battery: self.lid_battery.unwrap_or(fallback),
+            ac: self.lid_ac.unwrap_or(fallback),
+            // Like logind's HandleLidSwitchDocked, closing the lid of a docked laptop does nothing unless asked to
+            docked: self.lid_docked.unwrap_or(LidAction::Ignore)
+        }
+    }
+
+    /// Input device to read keys from, None to look for one
+    pub fn get_key_device(&self) -> Option<&str> {
+        self.key_device.as_ref().map(|d| &**d)
+    }
+
+    /// Keys lockd should take over from logind and what to do when they are pressed
+    pub fn get_key_actions(&self) -> &[(Key, KeyAction)] {
+        &self.key_actions
+    }
+
+    /// Commands to run when the machine shuts down, in order
+    pub fn get_pre_shutdown(&self) -> &[(String, Vec<String>)] {
+        &self.pre_shutdown
+    }
+
+    /// Whether to stop the lock screen before shutting down
+    pub fn get_pre_shutdown_timeout(&self) -> Duration {
+        self.pre_shutdown_timeout
+    }
+
+    pub fn get_stop_locker_on_shutdown(&self) -> bool {
+        self.stop_locker_on_shutdown
+    }
+
+    /// Maximum size of the history file in bytes and how many rotated files to keep
+    pub fn get_history_rotation(&self) -> (u64, u32) {
+        (self.history_max_size, self.history_keep)
+    }
+}
+
+// A command and its parameters
+fn plain_command(option: &str, block: &cfg::ConfigBlock) -> Result<(String, Vec<String>), ConfigError> {
+    if block.len() < 1 {
+        return Err(ConfigError::option(option, "You have to specify a command and optionally parameters"));
+    }
+    Ok((block.get(0).to_string(), (1..block.len()).map(|i| block.get(i).to_string()).collect()))
+}
+
+// A command and its parameters, which may contain placeholders, and settings for this locker in a block after them
+fn lock_command(option: &str, block: &cfg::ConfigBlock) -> Result<LockCommand, ConfigError> {
+    if block.len() < 1 {
+        return Err(ConfigError::option(option, "You have to specify a command and optionally parameters"));
+    }
+    let mut params = Vec::with_capacity(block.len() - 1);
+    for i in 1..block.len() {
+        match Template::parse(block.get(i)) {
+            Ok(t) => params.push(t),
+            Err(e) => return Err(ConfigError::option(option, &e))
+        }
+    }
+    let mut ready_delay = Duration::from_millis(500);
+    let mut unlock_signal = None;
+    for setting in block.inner() {
+        match setting.name() {
+            "ready_delay" => {
+                ready_delay = match setting.get_opt(0).and_then(|s| s.parse().ok()) {
+                    Some(ms) => Duration::from_millis(ms),
+                    None => return Err(ConfigError::option(&format!("{}.ready_delay", option), "Expected a number of milliseconds"))
+                }
+            },
+            "unlock_signal" => {
+                unlock_signal = match setting.get_opt(0).and_then(signal) {
+                    Some(s) => Some(s),
+                    None => return Err(ConfigError::option(&format!("{}.unlock_signal", option), "Expected a signal name like TERM or USR1, or a number"))
+                }
+            },
+            name => return Err(ConfigError::option(&format!("{}.{}", option, name), "Unknown locker setting, expected ready_delay or unlock_signal"))
+        }
+    }
+    Ok(LockCommand {
+        program: block.get(0).to_string(),
+        args: params,
+        ready_delay: ready_delay,
+        unlock_signal: unlock_signal
+    })
+}
+
+// Whether program can be started, as a path or by looking it up in path
+fn program_exists(program: &str, path: &str) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+    path.split(':').any(|dir| !dir.is_empty() && Path::new(dir).join(program).is_file())
+}
+
+fn signal(name: &str) -> Option<i32> {
+    let name = if name.starts_with("SIG") { &name[3..] } else { name };
+    match name {
+        "TERM" => Some(libc::SIGTERM),
+        "INT" => Some(libc::SIGINT),
+        "HUP" => Some(libc::SIGHUP),
+        "QUIT" => Some(libc::SIGQUIT),
+        "USR1" => Some(libc::SIGUSR1),
+        "USR2" => Some(libc::SIGUSR2),
+        "KILL" => Some(libc::SIGKILL),
+        _ => name.parse().ok()
+    }
+}
+
+fn lid_action(option: &str, name: &str) -> Result<LidAction, ConfigError> {
+    match LidAction::from_name(name) {
+        Some(action) => Ok(action),
+        None => Err(ConfigError::option(option, "Expected suspend, hibernate, hybrid-sleep, lock, lock-and-blank or ignore"))
     }
 }
 
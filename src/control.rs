@@ -1,9 +1,24 @@
 extern crate dbus;
 extern crate core;
+extern crate libc;
 
-use std::env;
+// Shared with lockd, we only need part of it
+#[allow(dead_code)]
+mod json;
+#[allow(dead_code)]
+mod history;
+mod localtime;
+mod report;
+
+use std::{env, thread, process};
+use std::io::{self, BufRead, Write};
+use std::os::unix::process::ExitStatusExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use core::result::Result;
-use dbus::{BusType, Connection, Message, Error};
+use dbus::{BusType, Connection, ConnectionItem, Message, MessageItem, Error};
+use dbus::arg::Append;
+
+const LID_ACTIONS: &'static [&'static str] = &["suspend", "hibernate", "hybrid-sleep", "lock", "lock-and-blank", "ignore"];
 
 fn main() {
     match exec() {
@@ -21,46 +36,51 @@ fn exec() -> Result<(), Error> {
     let c = Connection::get_private(BusType::Session).unwrap();
 
     if args.len() > 1 {
-        let r = try!(call(&c, method("GetSuspendOnLid")));
-        let lidaction: bool = r.get1().unwrap();
         let a: &str = &args[1];
         match a {
             "lock" => try!(basic_call(&c, method("Lock"))),
             "unlock" => try!(basic_call(&c, method("Unlock"))),
             "exit" => try!(basic_call(&c, method("Exit"))),
             "reload" => try!(basic_call(&c, method("Reload"))),
+            "rescan" => try!(basic_call(&c, method("Rescan"))),
             "lidaction" => 
                 if args.len() > 2 {
                     let b: &str = &args[2];
                     match b {
-                        "suspend" => try!(basic_call(&c, method("SetSuspendOnLid").append1(true))),
-                        "ignore" => try!(basic_call(&c, method("SetSuspendOnLid").append1(false))),
-                        "toggle" => try!(basic_call(&c, method("SetSuspendOnLid").append1(!lidaction))),
+                        "toggle" => {
+                            let lidaction = try!(get_lid_action(&c));
+                            try!(set_flag(&c, "SetLidAction", toggled_lid_action(&lidaction), &args[3..]))
+                        },
+                        _ if b == "auto" || LID_ACTIONS.contains(&b) => try!(set_flag(&c, "SetLidAction", b, &args[3..])),
                         _ => usage()
                     }
                 } else {
-                    if lidaction {
-                        println!("suspend")
-                    } else {
-                        println!("ignore")
-                    }
+                    println!("{}", try!(get_lid_action(&c)))
                 },
             "autolock" => if args.len() > 2 {
                 let b: &str = &args[2];
                 match b {
-                    "on" => try!(basic_call(&c, method("SetAutoLock").append1(true))),
-                    "off" => try!(basic_call(&c, method("SetAutoLock").append1(false))),
+                    "on" => try!(set_flag(&c, "SetAutoLock", true, &args[3..])),
+                    "off" => try!(set_flag(&c, "SetAutoLock", false, &args[3..])),
                     _ => usage()
                 }
             } else {
-                let r = try!(call(&c, method("GetAutoLock")));
-                if r.get1().unwrap() {
+                if try!(get_flag(&c, "GetAutoLock")) {
                     println!("on")
                 } else {
                     println!("off")
                 }
             },
             "perform_autolock" => try!(basic_call(&c, method("AutoLock"))),
+            "activity" => try!(basic_call(&c, method("Activity"))),
+            "suspend" | "hibernate" | "hybrid-sleep" => sleep(&c, a),
+            "status" => try!(status(&c, &args[2..])),
+            "monitor" => try!(monitor(&c, &args[2..])),
+            "bar" => try!(bar(&c, &args[2..])),
+            "exec" => try!(exec_inhibited(&c, &args[2..])),
+            "inhibitions" => try!(inhibitions(&c)),
+            "history" => try!(history(&c, &args[2..])),
+            "report" => try!(report(&c, &args[2..])),
             _ => usage()
         };
     } else {
@@ -69,6 +89,553 @@ fn exec() -> Result<(), Error> {
     Ok(())
 }
 
+fn status(con: &Connection, args: &[String]) -> Result<(), Error> {
+    let as_json = match args.get(0).map(|s| &**s) {
+        Some("--json") => true,
+        None => false,
+        _ => usage()
+    };
+    let r = try!(call(con, method("GetStatus")));
+    let items = r.get_items();
+    let status = match items.get(0) {
+        Some(&MessageItem::Array(ref entries, _)) => dict_entries(entries),
+        _ => Vec::new()
+    };
+    if as_json {
+        let fields: Vec<String> = status.iter().map(|&(ref k, v)| format!("{}:{}", json::escape(k), item_json(v))).collect();
+        println!("{{{}}}", fields.join(","));
+        return Ok(());
+    }
+
+    let get = |key: &str| status.iter().find(|&&(ref k, _)| k == key).map(|&(_, v)| v);
+    let flag = |key: &str| match get(key) {
+        Some(&MessageItem::Bool(b)) => b,
+        _ => false
+    };
+    let text = |key: &str| match get(key) {
+        Some(&MessageItem::Str(ref s)) => s.clone(),
+        _ => String::new()
+    };
+
+    if flag("locked") {
+        let since = match get("locked_since") {
+            Some(&MessageItem::UInt64(t)) => localtime::format(t, "%Y-%m-%d %H:%M:%S"),
+            _ => String::new()
+        };
+        println!("state: {} since {} ({})", if flag("locking") { "unlocking" } else { "locked" }, since, text("lock_reason"));
+    } else if flag("locking") {
+        println!("state: locking ({})", text("lock_reason"));
+    } else {
+        println!("state: unlocked");
+    }
+    let revert = |key: &str| match get(key) {
+        Some(&MessageItem::UInt64(secs)) if secs > 0 => format!(" (changes back in {})", report::format_duration(secs)),
+        _ => String::new()
+    };
+    println!("autolock: {}{}", if flag("autolock") { "on" } else { "off" }, revert("autolock_revert_in"));
+    let power = format!("{}{}", if flag("on_battery") { "on battery" } else { "on AC" }, if flag("docked") { ", docked" } else { "" });
+    if flag("lidaction_auto") {
+        println!("lidaction: {} (auto, {}){}", text("lidaction"), power, revert("lidaction_revert_in"));
+    } else {
+        println!("lidaction: {} ({}){}", text("lidaction"), power, revert("lidaction_revert_in"));
+    }
+    match get("inhibitors") {
+        Some(&MessageItem::Array(ref inhibitors, _)) if !inhibitors.is_empty() => {
+            let names: Vec<String> = inhibitors.iter().map(item_text).collect();
+            println!("inhibitors: {}", names.join(", "));
+        },
+        _ => println!("inhibitors: none")
+    }
+    if let Some(&MessageItem::Array(ref inhibitions, _)) = get("inhibitions") {
+        for inhibition in inhibitions {
+            println!("inhibited: {}", item_text(inhibition));
+        }
+    }
+    match get("locker_pid") {
+        Some(&MessageItem::UInt32(pid)) if pid != 0 => println!("locker pid: {}", pid),
+        _ => {}
+    }
+    println!("config: {}", text("config"));
+    Ok(())
+}
+
+fn inhibitions(con: &Connection) -> Result<(), Error> {
+    let r = try!(call(con, method("ListInhibitions")));
+    if let Some(&MessageItem::Array(ref inhibitions, _)) = r.get_items().get(0) {
+        for inhibition in inhibitions {
+            if let &MessageItem::Struct(ref fields) = inhibition {
+                let fields: Vec<String> = fields.iter().map(item_text).collect();
+                if fields.len() == 5 {
+                    println!("#{} {} by {} ({}): {}", fields[0], fields[1], fields[2], fields[4], fields[3]);
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+// PID of the child of lockctl exec, for forwarding signals
+static CHILD: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn forward_signal(signal: libc::c_int) {
+    let pid = CHILD.load(Ordering::SeqCst);
+    if pid != 0 {
+        unsafe { libc::kill(pid as libc::pid_t, signal) };
+    }
+}
+
+fn exec_inhibited(con: &Connection, args: &[String]) -> Result<(), Error> {
+    let mut kinds = Vec::new();
+    let mut why = None;
+    let mut it = args.iter();
+    let mut command = Vec::new();
+    while let Some(arg) = it.next() {
+        match &**arg {
+            "--autolock" => kinds.push("autolock"),
+            "--lid" => kinds.push("lid"),
+            "--why" => match it.next() {
+                Some(w) => why = Some(w.clone()),
+                None => usage()
+            },
+            "--" => {
+                command.extend(it.by_ref().cloned());
+            },
+            _ => {
+                command.push(arg.clone());
+                command.extend(it.by_ref().cloned());
+            }
+        }
+    }
+    if command.is_empty() {
+        usage();
+    }
+    if kinds.is_empty() {
+        kinds.push("autolock");
+    }
+    let why = why.unwrap_or_else(|| command.join(" "));
+
+    // lockd drops these as soon as our connection goes away, no matter how we exit
+    for kind in kinds {
+        try!(call(con, method("Inhibit").append3(kind, "lockctl exec", &*why)));
+    }
+
+    let mut child = match process::Command::new(&command[0]).args(&command[1..]).spawn() {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Could not run {}: {}", command[0], e);
+            process::exit(127);
+        }
+    };
+    CHILD.store(child.id() as usize, Ordering::SeqCst);
+    // SIGINT and SIGQUIT from the terminal reach the child on their own, forwarding
+    // them would deliver them twice
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+        for &signal in &[libc::SIGTERM, libc::SIGHUP, libc::SIGUSR1, libc::SIGUSR2] {
+            libc::signal(signal, forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t);
+        }
+    }
+
+    let status = child.wait().unwrap();
+    match (status.code(), status.signal()) {
+        (Some(code), _) => process::exit(code),
+        (None, Some(signal)) => process::exit(128 + signal),
+        (None, None) => process::exit(1)
+    }
+}
+
+/// Calls a setter, or its ...For variant if --for <duration> or --until <HH:MM> is given
+fn set_flag<A: Append>(con: &Connection, name: &str, value: A, args: &[String]) -> Result<(), Error> {
+    let secs = match (args.get(0).map(|s| &**s), args.get(1)) {
+        (None, _) => None,
+        (Some("--for"), Some(d)) => parse_duration(d),
+        (Some("--until"), Some(t)) => parse_time_of_day(t).map(|(h, m)| localtime::until(history::now(), h, m)),
+        _ => usage()
+    };
+    match secs {
+        Some(secs) => basic_call(con, method(&format!("{}For", name)).append2(value, secs)),
+        None if args.is_empty() => basic_call(con, method(name).append1(value)),
+        None => usage()
+    }
+}
+
+fn parse_time_of_day(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, ':');
+    let hour = parts.next().and_then(|h| h.parse().ok());
+    let minute = parts.next().map_or(Some(0), |m| m.parse().ok());
+    match (hour, minute) {
+        (Some(h), Some(m)) if h < 24 && m < 60 => Some((h, m)),
+        _ => None
+    }
+}
+
+fn get_flag(con: &Connection, name: &str) -> Result<bool, Error> {
+    let r = try!(call(con, method(name)));
+    Ok(r.get1().unwrap())
+}
+
+fn get_lid_action(con: &Connection) -> Result<String, Error> {
+    let r = try!(call(con, method("GetLidAction")));
+    Ok(r.get1::<&str>().unwrap().to_string())
+}
+
+/// Toggling goes from ignore to suspend and from every other action to ignore
+fn toggled_lid_action(current: &str) -> &'static str {
+    if current == "ignore" { "suspend" } else { "ignore" }
+}
+
+/// Unpacks the entries of an a{sv}
+fn dict_entries(entries: &[MessageItem]) -> Vec<(String, &MessageItem)> {
+    entries.iter().filter_map(|e| match e {
+        &MessageItem::DictEntry(ref k, ref v) => match (&**k, &**v) {
+            (&MessageItem::Str(ref k), &MessageItem::Variant(ref v)) => Some((k.clone(), &**v)),
+            (&MessageItem::Str(ref k), v) => Some((k.clone(), v)),
+            _ => None
+        },
+        _ => None
+    }).collect()
+}
+
+fn item_text(item: &MessageItem) -> String {
+    match *item {
+        MessageItem::Str(ref s) => s.clone(),
+        MessageItem::Variant(ref v) => item_text(v),
+        _ => item_json(item)
+    }
+}
+
+fn item_json(item: &MessageItem) -> String {
+    match *item {
+        MessageItem::Str(ref s) => json::escape(s),
+        MessageItem::Bool(b) => format!("{}", b),
+        MessageItem::Byte(n) => format!("{}", n),
+        MessageItem::Int16(n) => format!("{}", n),
+        MessageItem::Int32(n) => format!("{}", n),
+        MessageItem::Int64(n) => format!("{}", n),
+        MessageItem::UInt16(n) => format!("{}", n),
+        MessageItem::UInt32(n) => format!("{}", n),
+        MessageItem::UInt64(n) => format!("{}", n),
+        MessageItem::Double(n) => format!("{}", n),
+        MessageItem::Variant(ref v) => item_json(v),
+        MessageItem::Array(ref items, _) => {
+            if items.iter().all(|i| match *i { MessageItem::DictEntry(..) => true, _ => false }) && !items.is_empty() {
+                let fields: Vec<String> = dict_entries(items).iter()
+                    .map(|&(ref k, v)| format!("{}:{}", json::escape(k), item_json(v))).collect();
+                format!("{{{}}}", fields.join(","))
+            } else {
+                let items: Vec<String> = items.iter().map(item_json).collect();
+                format!("[{}]", items.join(","))
+            }
+        },
+        MessageItem::Struct(ref items) => {
+            let items: Vec<String> = items.iter().map(item_json).collect();
+            format!("[{}]", items.join(","))
+        },
+        _ => format!("null")
+    }
+}
+
+fn monitor(con: &Connection, args: &[String]) -> Result<(), Error> {
+    let as_json = match args.get(0).map(|s| &**s) {
+        Some("--json") => true,
+        None => false,
+        _ => usage()
+    };
+    watch(con, |event, fields| {
+        if as_json {
+            let mut out = format!("{{\"time\":{},\"event\":{}", history::now(), json::escape(event));
+            for &(name, ref value) in fields {
+                out.push_str(&format!(",{}:{}", json::escape(name), item_json(value)));
+            }
+            println!("{}}}", out);
+        } else {
+            let values: Vec<String> = fields.iter().map(|&(_, ref v)| item_text(v)).collect();
+            println!("{} {} {}", localtime::format(history::now(), "%Y-%m-%d %H:%M:%S"), event, values.join(" ").trim());
+        }
+    })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum BarFormat {
+    I3Blocks,
+    Waybar,
+    I3Bar
+}
+
+fn bar(con: &Connection, args: &[String]) -> Result<(), Error> {
+    let mut format = BarFormat::I3Blocks;
+    let mut click = None;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match &**arg {
+            "--format" => format = match it.next().map(|s| &**s) {
+                Some("i3blocks") => BarFormat::I3Blocks,
+                Some("waybar") => BarFormat::Waybar,
+                Some("i3bar-json") => BarFormat::I3Bar,
+                _ => usage()
+            },
+            "--click" => match it.next().and_then(|s| s.parse().ok()) {
+                Some(button) => click = Some(button),
+                None => usage()
+            },
+            _ => usage()
+        }
+    }
+
+    // waybar on-click and friends: just toggle and leave the printing to the running instance
+    if let Some(button) = click {
+        return bar_click(con, button);
+    }
+
+    // i3blocks runs us again with BLOCK_BUTTON set on every click
+    if let Some(button) = env::var("BLOCK_BUTTON").ok().and_then(|b| b.parse().ok()) {
+        try!(bar_click(con, button));
+        let autolock = try!(get_flag(con, "GetAutoLock"));
+        let lidaction = try!(get_lid_action(con));
+        bar_print(format, autolock, &lidaction, false);
+        return Ok(());
+    }
+
+    // Persistent i3blocks blocks and i3bar send click events on stdin
+    thread::spawn(move || {
+        let c = Connection::get_private(BusType::Session).unwrap();
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => break
+            };
+            let line = line.trim().trim_start_matches(',');
+            let button = match json::parse_object(line) {
+                Some(obj) => json::get(&obj, "button").and_then(|b| b.as_u64()),
+                None => line.parse().ok()
+            };
+            if let Some(button) = button {
+                if let Err(e) = bar_click(&c, button) {
+                    println!("DBus Error: {}", e);
+                }
+            }
+        }
+    });
+
+    if format == BarFormat::I3Bar {
+        println!("{{\"version\":1,\"click_events\":true}}");
+        println!("[");
+    }
+    let mut autolock = try!(get_flag(con, "GetAutoLock"));
+    let mut lidaction = try!(get_lid_action(con));
+    bar_print(format, autolock, &lidaction, true);
+    watch(con, |event, fields| {
+        match (event, fields.get(0)) {
+            ("autolock", Some(&(_, MessageItem::Bool(value)))) => autolock = value,
+            ("lidaction", Some(&(_, MessageItem::Str(ref value)))) => lidaction = value.clone(),
+            _ => return
+        }
+        bar_print(format, autolock, &lidaction, true);
+    })
+}
+
+/// Left click toggles autolock, right click toggles the lid action
+fn bar_click(con: &Connection, button: u64) -> Result<(), Error> {
+    match button {
+        1 => {
+            let autolock = try!(get_flag(con, "GetAutoLock"));
+            basic_call(con, method("SetAutoLock").append1(!autolock))
+        },
+        3 => {
+            let lidaction = try!(get_lid_action(con));
+            basic_call(con, method("SetLidAction").append1(toggled_lid_action(&lidaction)))
+        },
+        _ => Ok(())
+    }
+}
+
+/// persistent is for output that keeps coming, rather than one update per run
+fn bar_print(format: BarFormat, autolock: bool, lidaction: &str, persistent: bool) {
+    let full = format!("autolock {}, lid {}", if autolock { "on" } else { "off" }, lidaction);
+    let short = format!("{}/{}", if autolock { "on" } else { "off" }, lidaction);
+    // Only draw attention when something is switched off
+    let normal = autolock && lidaction != "ignore";
+    match format {
+        // With interval=persist every line replaces the whole block, so short text and color only work once
+        BarFormat::I3Blocks if persistent => println!("{}", full),
+        BarFormat::I3Blocks => {
+            println!("{}", full);
+            println!("{}", short);
+            println!("{}", if normal { "" } else { "#FFAA00" });
+        },
+        BarFormat::Waybar => {
+            println!("{{\"text\":{},\"alt\":{},\"tooltip\":{},\"class\":{}}}",
+                     json::escape(&short), json::escape(if normal { "normal" } else { "inhibited" }),
+                     json::escape(&full), json::escape(if normal { "normal" } else { "inhibited" }));
+        },
+        BarFormat::I3Bar => {
+            let color = if normal { String::new() } else { format!(",\"color\":\"#FFAA00\"") };
+            println!("[{{\"name\":\"lockd\",\"full_text\":{},\"short_text\":{}{}}}],",
+                     json::escape(&full), json::escape(&short), color);
+        }
+    }
+    // Bars read from a pipe, so make sure they see the update right away
+    let _ = io::stdout().flush();
+}
+
+/// Calls handler for every signal lockd emits, until the connection goes away
+fn watch<F: FnMut(&str, &[(&str, MessageItem)])>(con: &Connection, mut handler: F) -> Result<(), Error> {
+    try!(con.add_match("type='signal',interface='de.kilobyte22.lockd.Control'"));
+    for item in con.iter(60_000) {
+        if let ConnectionItem::Signal(msg) = item {
+            let member = match msg.member() {
+                Some(m) => m.to_string(),
+                None => continue
+            };
+            let items = msg.get_items();
+            let arg = |i: usize| items.get(i).cloned().unwrap_or(MessageItem::Str(String::new()));
+            let (event, fields) = match &*member {
+                "Locked" => ("locked", vec![("reason", arg(0))]),
+                "Unlocked" => ("unlocked", vec![("reason", arg(0))]),
+                "AutoLockChanged" => ("autolock", vec![("enabled", arg(0))]),
+                "LidActionChanged" => ("lidaction", vec![("action", arg(0))]),
+                "Suspending" => ("suspend", vec![]),
+                "Resumed" => ("resume", vec![("slept", arg(0))]),
+                "ConfigReloaded" => ("config_reload", vec![]),
+                _ => continue
+            };
+            handler(event, &fields);
+        }
+    }
+    Ok(())
+}
+
+fn history(con: &Connection, args: &[String]) -> Result<(), Error> {
+    let mut since = 0;
+    let mut as_json = false;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match &**arg {
+            "--json" => as_json = true,
+            "--since" => match it.next().and_then(|s| parse_since(s)) {
+                Some(t) => since = t,
+                None => usage()
+            },
+            _ => usage()
+        }
+    }
+
+    for entry in try!(load_history(con, since)) {
+        let (time, event, reason, duration) = entry;
+        if as_json {
+            println!("{{\"time\":{},\"event\":{},\"reason\":{},\"duration\":{}}}",
+                     time, json::escape(&event), json::escape(&reason), duration);
+        } else {
+            let mut line = format!("{}  {:<14} {}", localtime::format(time, "%Y-%m-%d %H:%M:%S"), event, reason);
+            if duration > 0 {
+                line.push_str(&format!(" (after {})", report::format_duration(duration)));
+            }
+            println!("{}", line.trim_end());
+        }
+    }
+    Ok(())
+}
+
+fn report(con: &Connection, args: &[String]) -> Result<(), Error> {
+    let mut since = 0;
+    let mut threshold = 15 * 60;
+    let mut format = report::Format::Table;
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        match &**arg {
+            "--since" => match it.next().and_then(|s| parse_since(s)) {
+                Some(t) => since = t,
+                None => usage()
+            },
+            "--threshold" => match it.next().and_then(|s| parse_duration(s)) {
+                Some(t) => threshold = t,
+                None => usage()
+            },
+            "--format" => format = match it.next().map(|s| &**s) {
+                Some("table") => report::Format::Table,
+                Some("csv") => report::Format::Csv,
+                Some("json") => report::Format::Json,
+                _ => usage()
+            },
+            _ => usage()
+        }
+    }
+
+    let entries = try!(load_history(con, since));
+    report::print(&report::days(&entries, history::now(), threshold), format);
+    Ok(())
+}
+
+/// History from lockd, or straight from its file when lockd is not running
+fn load_history(con: &Connection, since: u64) -> Result<Vec<(u64, String, String, u64)>, Error> {
+    match call(con, method("GetHistory").append1(since)) {
+        Ok(r) => Ok(history_entries(&r)),
+        Err(ref e) if e.name() == Some("org.freedesktop.DBus.Error.ServiceUnknown") => {
+            Ok(history::read(since).into_iter().map(|e| (e.time, e.event.name().to_string(), e.reason, e.duration)).collect())
+        },
+        Err(e) => Err(e)
+    }
+}
+
+fn history_entries(m: &Message) -> Vec<(u64, String, String, u64)> {
+    let mut ret = Vec::new();
+    if let Some(&MessageItem::Array(ref entries, _)) = m.get_items().get(0) {
+        for entry in entries {
+            if let &MessageItem::Struct(ref fields) = entry {
+                match (fields.get(0), fields.get(1), fields.get(2), fields.get(3)) {
+                    (Some(&MessageItem::UInt64(time)), Some(&MessageItem::Str(ref event)),
+                     Some(&MessageItem::Str(ref reason)), Some(&MessageItem::UInt64(duration))) => {
+                        ret.push((time, event.clone(), reason.clone(), duration));
+                    },
+                    _ => {}
+                }
+            }
+        }
+    }
+    ret
+}
+
+/// Parses durations like 90, 90s, 45m, 2h, 1d or 1w into seconds
+fn parse_duration(s: &str) -> Option<u64> {
+    let split = s.find(|c: char| !c.is_digit(10)).unwrap_or(s.len());
+    let n: u64 = match s[..split].parse() {
+        Ok(n) => n,
+        Err(_) => return None
+    };
+    let unit = match &s[split..] {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None
+    };
+    n.checked_mul(unit)
+}
+
+/// Accepts a unix timestamp, today, yesterday, or a duration meaning that long ago
+fn parse_since(s: &str) -> Option<u64> {
+    match s {
+        "today" => Some(localtime::start_of_day(history::now())),
+        "yesterday" => Some(localtime::start_of_day(localtime::start_of_day(history::now()) - 1)),
+        _ if s.chars().all(|c| c.is_digit(10)) => s.parse().ok(),
+        _ => parse_duration(s).map(|d| history::now().saturating_sub(d))
+    }
+}
+
+fn sleep(con: &Connection, what: &str) {
+    // Waits for the lock screen and logind, so give it more time than other calls
+    match con.send_with_reply_and_block(method("Sleep").append1(what), 30_000) {
+        Ok(_) => {},
+        Err(e) => {
+            println!("Could not {}: {}", what, e.message().unwrap_or("unknown error"));
+            process::exit(1);
+        }
+    }
+}
+
 fn basic_call(con: &Connection, m: Message) -> Result<(), Error> {
     match call(con, m) {
         Ok(_) => Ok(()),
@@ -89,17 +656,36 @@ fn method(name: &str) -> Message {
     ).unwrap()
 }
 
-fn usage() {
+/// Prints the commands and exits, lockctl was called wrong
+fn usage() -> ! {
     let usage = r#"
 Commands:
 
 lock - instantly locks the screen
 unlock - instantly unlocks the screen
-lidaction [suspend|ignore|toggle] - gets or sets the lid action
-autolock [on|off] - gets or sets the autolock state
+lidaction [<action>|auto|toggle [--for <duration>|--until <HH:MM>]] - gets or sets the lid action
+    <action> is suspend, hibernate, hybrid-sleep, lock, lock-and-blank or ignore,
+    auto follows the lidaction block of the configuration, toggle switches between ignore and suspend
+autolock [on|off [--for <duration>|--until <HH:MM>]] - gets or sets the autolock state
+    with --for or --until the setting changes back on its own, e.g. autolock off --for 45m
 perform_autolock - locks the screen if autolock is enabled
+activity - cancels a pending autolock
+suspend, hibernate, hybrid-sleep - locks the screen and puts the machine to sleep once the lock screen is up
 exit - exit the daemon cleanly
-reload - reload the configuration file"#;
+reload - reload the configuration file
+rescan - look for a lock screen started without lockd and take it over
+status [--json] - shows the complete state of the daemon
+monitor [--json] - prints a line for every event until interrupted
+inhibitions - lists which clients currently inhibit autolock, lid suspend or locking before sleep
+exec [--autolock] [--lid] [--why <reason>] [--] <command> [args...]
+    - runs command with autolock (the default) and/or lid suspend inhibited until it exits
+bar [--format i3blocks|waybar|i3bar-json] [--click <button>]
+    - keeps printing the autolock and lid state for status bars. Button 1 toggles autolock, button 3 the lid action
+history [--since <time>] [--json] - shows past locks, unlocks and suspends
+    <time> is a unix timestamp, today, yesterday or a duration like 2h or 3d
+report [--since <time>] [--threshold <duration>] [--format table|csv|json]
+    - shows active and away time per day, listing breaks longer than the threshold (default 15m)"#;
     println!("Usage {} <command> [args...]", env::args().next().unwrap());
     println!("{}", usage);
+    process::exit(2);
 }
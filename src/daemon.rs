@@ -4,8 +4,9 @@ extern crate dbus;
 
 use std::sync::mpsc;
 use std::sync::mpsc::{Sender, Receiver};
-use std::{thread, fs, path, env};
+use std::{thread, fs, path, env, process};
 use std::io::{Result as IOResult, Write, Read};
+use std::time::Duration;
 
 //mod config;
 mod msg;
@@ -14,31 +15,147 @@ mod inhibit;
 mod react;
 mod api;
 mod config;
+mod history;
+mod json;
+mod timer;
+mod power;
+mod keys;
+mod procfs;
+mod environment;
+mod template;
+mod notify;
+mod backlight;
+mod logind;
+// Shared with lockctl, we only need part of it
+#[allow(dead_code)]
+mod localtime;
 
 macro_rules! dbgprintln {
     ($fmt:expr) => (if cfg!(debug){println!($fmt)});
     ($fmt:expr, $($arg:tt)*) => (if cfg!(debug){println!($fmt, $($arg)*)});
 }
 
-use msg::{LockMessage, InhibitMessage, CoreMessage, CoreFlag};
+use msg::{LockMessage, InhibitMessage, ApiMessage, TimerMessage, Timer, CoreMessage, CoreFlag, LockReason, LidAction, LidPolicy, Key, KeyAction, KeysMessage, NotifyMessage, AutoLockWarning, Backlight, BlankAction, InhibitKind, Inhibition, Status};
+use history::{Event, HistoryMessage};
+use dbus::BusType;
 
 struct ActorMainHandles {
+    // For threads core starts
+    core: Sender<CoreMessage>,
     lockscreen: Sender<LockMessage>,
-    inhibitors: Sender<InhibitMessage>
+    inhibitors: Sender<InhibitMessage>,
+    history: Sender<HistoryMessage>,
+    api: Sender<ApiMessage>,
+    timer: Sender<TimerMessage>,
+    keys: Sender<KeysMessage>,
+    notify: Sender<NotifyMessage>
 }
 
 struct State {
     locked: bool,
+    autolock: bool,
+    // Value to go back to and when
+    autolock_revert: Option<(bool, Duration)>,
+    autolock_warning: AutoLockWarning,
+    // When autolock locks unless there is activity first
+    autolock_pending: Option<Duration>,
+    // The warning ended in a lock, run its cancel_command on unlock
+    warning_to_undo: bool,
+    grace_period: Duration,
+    // Until when activity unlocks again, only set after autolock
+    grace_until: Option<Duration>,
+    backlight: backlight::Control,
+    // Backlight device and brightness to go back to
+    dimmed: Option<(String, u32)>,
+    // Set through lockctl, None follows the policy
+    lid_action: Option<LidAction>,
+    lid_revert: Option<(Option<LidAction>, Duration)>,
+    lid_policy: LidPolicy,
+    docked: bool,
+    on_battery: bool,
+    // Whether we hold the lid switch block inhibitor
     inhibit_lid: bool,
+    // Whether we hold the sleep delay inhibitor
+    holds_delay: bool,
+    // Effective autolock and lid action as last told to D-Bus clients
+    announced: (bool, LidAction),
+    // Turn the screen off as soon as the lock screen is up
+    blank_on_lock: bool,
+    // Zero never blanks while locked
+    blank_after: Duration,
+    blank_action: BlankAction,
+    // When to turn the display off, while locked
+    blank_at: Option<Duration>,
+    blanked: bool,
+    // Backlight device and brightness to go back to after blanking with the backlight
+    unblank_to: Option<(String, u32)>,
+    // Go to sleep as soon as the lock screen is up
+    sleep_on_lock: Option<power::SleepKind>,
+    // Clients waiting to hear how going to sleep went
+    sleep_replies: Vec<Sender<Result<(), String>>>,
+    // Why the locker failed, until it is gone
+    locker_error: Option<String>,
+    key_actions: Vec<(Key, KeyAction)>,
+    // Keys our input devices can report
+    keys_available: Vec<Key>,
+    // Keys we hold a block inhibitor for
+    key_blocks: Vec<Key>,
     locking: bool,
     should_exit: bool,
-    autolock: bool
+    // Between PrepareForShutdown and the end, which may also be a cancel
+    shutting_down: bool,
+    // The locker was stopped for the shutdown, lock like this again if it gets cancelled
+    stopped_for_shutdown: Option<LockReason>,
+    // Lock again once the locker is gone, with this reason
+    relock: Option<LockReason>,
+    pre_shutdown: Vec<(String, Vec<String>)>,
+    pre_shutdown_timeout: Duration,
+    // While the pre_shutdown hooks run, when to stop waiting for them
+    pre_shutdown_until: Option<Duration>,
+    // Numbers shutdowns, so hooks still running from a cancelled one are not taken for those of the next
+    shutdowns: u32,
+    stop_locker_on_shutdown: bool,
+    lock_reason: Option<LockReason>,
+    // Set when we asked the locker to go away, None means it went away on its own
+    unlock_reason: Option<&'static str>,
+    locked_since: u64,
+    suspended_since: Option<u64>,
+    locker_pid: Option<u32>,
+    // To tell the locker apart from a process that got its PID later
+    locker_starttime: Option<u64>,
+    // Total time asleep since boot when we last looked
+    slept_total: Option<u64>,
+    inhibitions: Vec<Inhibition>,
+    next_cookie: u32
+}
+
+impl State {
+    fn inhibited(&self, kind: InhibitKind) -> bool {
+        self.inhibitions.iter().any(|i| i.kind == kind)
+    }
+
+    fn effective_autolock(&self) -> bool {
+        self.autolock && !self.inhibited(InhibitKind::AutoLock)
+    }
+
+    fn effective_lid_action(&self) -> LidAction {
+        if self.inhibited(InhibitKind::Lid) {
+            LidAction::Ignore
+        } else {
+            self.lid_action.unwrap_or(self.lid_policy.action(self.docked, self.on_battery))
+        }
+    }
 }
 
 fn main() {
     let (core_send, core_recv) = mpsc::channel();
     let (inh_send, inh_recv) = mpsc::channel();
     let (lock_send, lock_recv) = mpsc::channel();
+    let (hist_send, hist_recv) = mpsc::channel();
+    let (api_send, api_recv) = mpsc::channel();
+    let (timer_send, timer_recv) = mpsc::channel();
+    let (keys_send, keys_recv) = mpsc::channel();
+    let (notify_send, notify_recv) = mpsc::channel();
 
     let core = core_send.clone();
     thread::spawn(||{
@@ -54,12 +171,33 @@ fn main() {
     });
     let core = core_send.clone();
     thread::spawn(||{
-        api::actor_api(core);
+        api::actor_api(core, api_recv);
+    });
+    thread::spawn(||{
+        history::actor_history(hist_recv);
+    });
+    let core = core_send.clone();
+    thread::spawn(||{
+        timer::actor_timer(core, timer_recv);
+    });
+    let core = core_send.clone();
+    thread::spawn(||{
+        keys::actor_keys(core, keys_recv);
+    });
+    let core = core_send.clone();
+    thread::spawn(||{
+        notify::actor_notify(core, notify_recv);
     });
 
     let handles = ActorMainHandles {
+        core: core_send,
         lockscreen: lock_send,
-        inhibitors: inh_send
+        inhibitors: inh_send,
+        history: hist_send,
+        api: api_send,
+        timer: timer_send,
+        keys: keys_send,
+        notify: notify_send
     };
 
     actor_main(handles, core_recv);
@@ -95,18 +233,19 @@ fn write_file(path: &path::Path, content: &str) -> IOResult<()> {
     Ok(())
 }
 
-fn load_config() -> Option<config::Config> {
-    let mut pathstr = String::new();
-    let path = match env::var("HOME") {
-        Ok(home) => {
-            pathstr = format!("{}/.config/lockd/main.cfg", home);
-            path::Path::new(&pathstr)
-        },
+fn config_path() -> path::PathBuf {
+    match env::var("HOME") {
+        Ok(home) => path::PathBuf::from(format!("{}/.config/lockd/main.cfg", home)),
         Err(e) => {
             println!("Warning: Could not get $HOME: {}, defaulting to config file /etc/lockd.cfg", e);
-            path::Path::new("/etc/lockd.cfg")
+            path::PathBuf::from("/etc/lockd.cfg")
         }
-    };
+    }
+}
+
+fn load_config() -> Option<config::Config> {
+    let pathbuf = config_path();
+    let path = pathbuf.as_path();
 
     let data = match fs::metadata(path) {
         Ok(md) => Some(md),
@@ -134,8 +273,14 @@ fn load_config() -> Option<config::Config> {
             let mut f = fs::File::open(path).expect("Could not open config file");
             let mut s = String::new();
             f.read_to_string(&mut s);
-            let cfg = config::Config::parse(s).unwrap();
-            Some(cfg)
+            // Also catches broken lock_cmd placeholders, so a reload keeps the old configuration
+            match config::Config::parse(s) {
+                Ok(cfg) => Some(cfg),
+                Err(e) => {
+                    println!("Error in configuration file {}: {}", path.to_str().unwrap(), e);
+                    None
+                }
+            }
         },
         None => {
             println!("Warning: taking default config but not writing it");
@@ -148,99 +293,852 @@ fn load_config() -> Option<config::Config> {
 
 }
 
-fn apply_config(config: config::Config, handles: &ActorMainHandles) {
-    let cmd = config.get_lock_command();
-    let lcmd = cmd.0;
-    let lparam = cmd.1.iter().map(|s| s.to_string()).collect();
-    handles.lockscreen.send(LockMessage::SetLockscreen(lcmd.to_string(), lparam));
+fn apply_config(config: config::Config, state: &mut State, handles: &ActorMainHandles) {
+    handles.lockscreen.send(LockMessage::SetLockscreen(config.get_lockers())).unwrap();
+    let (signal, timeout) = config.get_unlock_policy();
+    handles.lockscreen.send(LockMessage::SetUnlockPolicy(signal, timeout)).unwrap();
+    handles.lockscreen.send(LockMessage::SetEnvironment(config.get_lock_environment().clone())).unwrap();
+    let (max_size, keep) = config.get_history_rotation();
+    handles.history.send(HistoryMessage::SetRotation(max_size, keep)).unwrap();
+    state.lid_policy = config.get_lid_policy();
+    state.autolock_warning = config.get_autolock_warning().clone();
+    state.grace_period = config.get_grace_period();
+    state.backlight = backlight::Control::new(config.get_backlight().clone());
+    let (blank_after, blank_action) = config.get_blank();
+    state.blank_after = blank_after;
+    state.blank_action = blank_action.clone();
+    state.key_actions = config.get_key_actions().to_vec();
+    state.pre_shutdown = config.get_pre_shutdown().to_vec();
+    state.pre_shutdown_timeout = config.get_pre_shutdown_timeout();
+    state.stop_locker_on_shutdown = config.get_stop_locker_on_shutdown();
+    if state.key_actions.is_empty() {
+        handles.keys.send(KeysMessage::Close).unwrap();
+    } else {
+        handles.keys.send(KeysMessage::SetDevice(config.get_key_device().map(|d| d.to_string()))).unwrap();
+    }
+    update_flags(state, handles);
+    update_keys(state, handles);
+}
+
+fn record(handles: &ActorMainHandles, event: Event, reason: &str, duration: u64) {
+    handles.history.send(HistoryMessage::Record(history::Entry::new(event, reason, duration))).unwrap();
+}
+
+fn lock(state: &mut State, handles: &ActorMainHandles, reason: LockReason) {
+    end_autolock_warning(state, handles);
+    handles.lockscreen.send(LockMessage::Lock(reason)).unwrap();
+    state.locking = true;
+    state.lock_reason = Some(reason);
+    record(handles, Event::Locking, reason.name(), 0);
+}
+
+/// Brings logind inhibitors and D-Bus clients in line with the settings and inhibitions
+fn update_flags(state: &mut State, handles: &ActorMainHandles) {
+    let autolock = state.effective_autolock();
+    let lid_action = state.effective_lid_action();
+    // Suspending is left to logind, for everything else we take over the lid switch.
+    // logind ignores the lid while docked by default, so a docked suspend has to be done by us.
+    let inhibit_lid = lid_action != LidAction::Suspend || state.docked;
+    if inhibit_lid != state.inhibit_lid {
+        state.inhibit_lid = inhibit_lid;
+        if state.inhibit_lid {
+            handles.inhibitors.send(InhibitMessage::CreateBlock).unwrap();
+        } else {
+            handles.inhibitors.send(InhibitMessage::ReleaseBlock).unwrap();
+        }
+    }
+    if autolock != state.announced.0 {
+        handles.api.send(ApiMessage::AutoLockChanged(autolock)).unwrap();
+    }
+    if lid_action != state.announced.1 {
+        handles.api.send(ApiMessage::LidActionChanged(lid_action)).unwrap();
+    }
+    state.announced = (autolock, lid_action);
+    update_delay(state, handles);
+}
+
+/// We only need to delay sleep if we are going to put up the lock screen
+fn update_delay(state: &mut State, handles: &ActorMainHandles) {
+    let delay = !state.locked && !state.inhibited(InhibitKind::SleepLock);
+    if delay != state.holds_delay {
+        state.holds_delay = delay;
+        if delay {
+            handles.inhibitors.send(InhibitMessage::CreateDelay).unwrap();
+        } else {
+            handles.inhibitors.send(InhibitMessage::ReleaseDelay).unwrap();
+        }
+    }
+}
+
+fn set_autolock(state: &mut State, handles: &ActorMainHandles, value: bool) {
+    state.autolock = value;
+    update_flags(state, handles);
+}
+
+fn set_lid_action(state: &mut State, handles: &ActorMainHandles, value: Option<LidAction>) {
+    state.lid_action = value;
+    update_flags(state, handles);
+}
+
+/// Carries out the lid action once the lid got closed, only called while we hold the lid switch
+fn lid_closed(state: &mut State, handles: &ActorMainHandles) {
+    let kind = match state.effective_lid_action() {
+        LidAction::Suspend => power::SleepKind::Suspend,
+        LidAction::Hibernate => power::SleepKind::Hibernate,
+        LidAction::HybridSleep => power::SleepKind::HybridSleep,
+        LidAction::Lock => {
+            if !state.locked && !state.locking {
+                lock(state, handles, LockReason::Lid);
+            }
+            return;
+        },
+        LidAction::LockAndBlank => {
+            if state.locked {
+                blank(state);
+            } else {
+                state.blank_on_lock = true;
+                if !state.locking {
+                    lock(state, handles, LockReason::Lid);
+                }
+            }
+            return;
+        },
+        LidAction::Ignore => return
+    };
+    sleep(kind, Vec::new());
+}
+
+fn sleep(kind: power::SleepKind, replies: Vec<Sender<Result<(), String>>>) {
+    // logind blocks until the sleep delay inhibitor is released, which needs us
+    thread::spawn(move || {
+        let result = power::sleep(kind);
+        if let Err(ref e) = result {
+            println!("Warning: could not {}: {}", kind.name(), e);
+        }
+        for reply in replies {
+            let _ = reply.send(result.clone());
+        }
+    });
+}
+
+/// Goes to sleep right away if locked, otherwise once the lock screen is up
+fn lock_then_sleep(state: &mut State, handles: &ActorMainHandles, kind: power::SleepKind, reason: LockReason) {
+    // Unlike going to sleep directly this locks even if sleep-lock is inhibited
+    if state.locked {
+        let replies = state.sleep_replies.drain(..).collect();
+        sleep(kind, replies);
+    } else {
+        state.sleep_on_lock = Some(kind);
+        if !state.locking {
+            lock(state, handles, reason);
+        }
+    }
+}
+
+/// Takes over the keys that have an action and that we can actually see being pressed
+fn update_keys(state: &mut State, handles: &ActorMainHandles) {
+    for &key in &[Key::Power, Key::Suspend, Key::Hibernate] {
+        let block = state.keys_available.contains(&key) && state.key_actions.iter().any(|&(k, _)| k == key);
+        if block != state.key_blocks.contains(&key) {
+            if block {
+                state.key_blocks.push(key);
+                handles.inhibitors.send(InhibitMessage::CreateKeyBlock(key)).unwrap();
+            } else {
+                state.key_blocks.retain(|&k| k != key);
+                handles.inhibitors.send(InhibitMessage::ReleaseKeyBlock(key)).unwrap();
+            }
+        }
+    }
+}
+
+fn key_pressed(state: &mut State, handles: &ActorMainHandles, key: Key) {
+    // Without the inhibitor logind already does its own thing
+    if !state.key_blocks.contains(&key) {
+        return;
+    }
+    let action = match state.key_actions.iter().find(|&&(k, _)| k == key) {
+        Some(&(_, ref action)) => action.clone(),
+        None => return
+    };
+    match action {
+        KeyAction::Lock => {
+            if !state.locked && !state.locking {
+                lock(state, handles, LockReason::Key);
+            }
+        },
+        KeyAction::Suspend => sleep(power::SleepKind::Suspend, Vec::new()),
+        KeyAction::Hibernate => sleep(power::SleepKind::Hibernate, Vec::new()),
+        KeyAction::LockThenSuspend => lock_then_sleep(state, handles, power::SleepKind::Suspend, LockReason::Key),
+        KeyAction::Command(cmd, params) => spawn_command(&cmd, &params),
+        KeyAction::Ignore => {}
+    }
+}
+
+fn spawn_command(cmd: &str, params: &[String]) {
+    match process::Command::new(cmd).args(params).spawn() {
+        // Reap it in the background
+        Ok(mut child) => { thread::spawn(move || child.wait()); },
+        Err(e) => println!("Warning: could not run {}: {}", cmd, e)
+    }
+}
+
+/// Tells the user the screen is about to lock. Activity until the timer runs out cancels it.
+fn warn_autolock(state: &mut State, handles: &ActorMainHandles) {
+    let duration = state.autolock_warning.duration;
+    let at = timer::now() + duration;
+    state.autolock_pending = Some(at);
+    handles.timer.send(TimerMessage::Set(Timer::AutoLockWarning, at)).unwrap();
+    if state.autolock_warning.notify {
+        let body = format!("Locking in {} s", duration.as_secs());
+        handles.notify.send(NotifyMessage::Show(format!("Screen is about to lock"), body, duration.as_secs() as u32 * 1000)).unwrap();
+    }
+    if let Some((ref cmd, ref params)) = state.autolock_warning.command {
+        spawn_command(cmd, params);
+    }
+    if let Some(percent) = state.autolock_warning.dim {
+        dim(state, percent);
+    }
+}
+
+fn dim(state: &mut State, percent: u32) {
+    // Keep the brightness from before the first dim to go back to
+    if state.dimmed.is_some() {
+        return;
+    }
+    let (name, brightness) = match backlight::current(state.backlight.config()) {
+        Some(b) => b,
+        None => {
+            println!("Warning: no backlight found in {}", state.backlight.config().sysfs);
+            return;
+        }
+    };
+    match state.backlight.set(&name, brightness * percent / 100) {
+        Ok(()) => state.dimmed = Some((name, brightness)),
+        Err(e) => println!("Warning: could not dim the backlight: {}", e)
+    }
+}
+
+/// Puts the backlight back to where it was before dimming
+fn restore_backlight(state: &mut State) {
+    if let Some((name, brightness)) = state.dimmed.take() {
+        if let Err(e) = state.backlight.set(&name, brightness) {
+            println!("Warning: could not restore the backlight: {}", e);
+        }
+    }
+}
+
+/// Drops a pending autolock, returns whether there was one
+fn end_autolock_warning(state: &mut State, handles: &ActorMainHandles) -> bool {
+    if state.autolock_pending.take().is_none() {
+        return false;
+    }
+    handles.timer.send(TimerMessage::Cancel(Timer::AutoLockWarning)).unwrap();
+    if state.autolock_warning.notify {
+        handles.notify.send(NotifyMessage::Close).unwrap();
+    }
+    true
+}
+
+/// Turns the display off
+fn blank(state: &mut State) {
+    state.blank_at = None;
+    if state.blanked {
+        return;
+    }
+    match state.blank_action.clone() {
+        BlankAction::Command((cmd, params), _) => spawn_command(&cmd, &params),
+        BlankAction::Backlight => {
+            let (name, brightness) = match backlight::current(state.backlight.config()) {
+                Some(b) => b,
+                None => {
+                    println!("Warning: no backlight found in {}", state.backlight.config().sysfs);
+                    return;
+                }
+            };
+            match state.backlight.set(&name, 0) {
+                Ok(()) => state.unblank_to = Some((name, brightness)),
+                Err(e) => {
+                    println!("Warning: could not turn the backlight off: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+    state.blanked = true;
+}
+
+/// Turns the display back on if we turned it off
+fn unblank(state: &mut State) {
+    if !state.blanked {
+        return;
+    }
+    state.blanked = false;
+    match state.blank_action.clone() {
+        BlankAction::Command(_, Some((cmd, params))) => spawn_command(&cmd, &params),
+        BlankAction::Command(_, None) => {},
+        BlankAction::Backlight => if let Some((name, brightness)) = state.unblank_to.take() {
+            if let Err(e) = state.backlight.set(&name, brightness) {
+                println!("Warning: could not turn the backlight back on: {}", e);
+            }
+        }
+    }
+}
+
+/// Turns the display off blank_after from now, if that is set
+fn schedule_blank(state: &mut State, handles: &ActorMainHandles) {
+    if state.blank_after > Duration::from_secs(0) {
+        let at = timer::now() + state.blank_after;
+        state.blank_at = Some(at);
+        handles.timer.send(TimerMessage::Set(Timer::Blank, at)).unwrap();
+    }
+}
+
+/// The timer::now() that far from now, never if that is past what the clock can hold
+fn deadline(after: Duration) -> Duration {
+    timer::now().checked_add(after).unwrap_or(Duration::new(u64::max_value(), 0))
+}
+
+fn remaining<T>(revert: Option<(T, Duration)>) -> u64 {
+    match revert {
+        Some((_, at)) => {
+            let now = timer::now();
+            if at > now { (at - now).as_secs() + 1 } else { 0 }
+        },
+        None => 0
+    }
+}
+
+fn unlock(state: &mut State, handles: &ActorMainHandles, reason: &'static str) {
+    handles.lockscreen.send(LockMessage::Unlock).unwrap();
+    state.locking = true;
+    state.unlock_reason = Some(reason);
+}
+
+/// Whether pid is the locker we are waiting for, 0 being one that could not be started
+fn is_current_locker(state: &State, pid: u32) -> bool {
+    match state.locker_pid {
+        Some(p) => p == pid,
+        None => pid == 0 && state.locking
+    }
+}
+
+/// Updates the state once the locker is gone
+fn locker_gone(state: &mut State, handles: &ActorMainHandles) {
+    let was_up = state.locked;
+    state.locked = false;
+    state.locking = false;
+    state.blank_on_lock = false;
+    state.sleep_on_lock = None;
+    // The lock screen never came up
+    let error = match state.locker_error.take() {
+        Some(e) => format!("the lock screen exited before it was up: {}", e),
+        None => format!("the lock screen exited before it was up")
+    };
+    for reply in state.sleep_replies.drain(..) {
+        let _ = reply.send(Err(error.clone()));
+    }
+    // Nobody heard of the lock if the locker exited before it was up
+    if was_up {
+        let reason = state.unlock_reason.unwrap_or("user");
+        record(handles, Event::Unlocked, reason, history::now().saturating_sub(state.locked_since));
+        handles.api.send(ApiMessage::Unlocked(reason.to_string())).unwrap();
+    }
+    state.lock_reason = None;
+    state.unlock_reason = None;
+    state.locker_pid = None;
+    state.locker_starttime = None;
+    state.grace_until = None;
+    if state.blank_at.take().is_some() {
+        handles.timer.send(TimerMessage::Cancel(Timer::Blank)).unwrap();
+    }
+    // Blanking may have started from the dimmed brightness
+    unblank(state);
+    restore_backlight(state);
+    if state.warning_to_undo {
+        state.warning_to_undo = false;
+        if let Some((ref cmd, ref params)) = state.autolock_warning.cancel_command {
+            spawn_command(cmd, params);
+        }
+    }
+    if state.should_exit {
+        exit(handles);
+    }
+    if state.shutting_down && state.pre_shutdown_until.is_none() {
+        // Nothing left to do before the shutdown, we stay around in case it gets cancelled
+        handles.inhibitors.send(InhibitMessage::ReleaseShutdownDelay).unwrap();
+    }
+    if let Some(reason) = state.relock.take() {
+        lock(state, handles, reason);
+    }
+    update_delay(state, handles);
+}
+
+/// The lock screen is up, whether we started it or adopted it
+fn locker_up(state: &mut State, handles: &ActorMainHandles, pid: u32) {
+    state.locked = true;
+    state.locker_pid = Some(pid);
+    state.locker_starttime = procfs::starttime(pid);
+    state.locking = false;
+    state.locked_since = history::now();
+    // Never for locks the user asked for or that protect a sleeping machine
+    state.grace_until = match state.lock_reason {
+        Some(LockReason::AutoLock) if state.grace_period > Duration::from_secs(0) => Some(timer::now() + state.grace_period),
+        _ => None
+    };
+    record(handles, Event::Locked, state.lock_reason.map_or("", |r| r.name()), 0);
+    if let Some(reason) = state.lock_reason {
+        handles.api.send(ApiMessage::Locked(reason)).unwrap();
+    }
+    if state.blank_on_lock {
+        state.blank_on_lock = false;
+        blank(state);
+    } else {
+        schedule_blank(state, handles);
+    }
+    if let Some(kind) = state.sleep_on_lock.take() {
+        sleep(kind, state.sleep_replies.drain(..).collect());
+    }
+    update_delay(state, handles);
+}
+
+/// Lets go of everything we hold at logind before exiting
+fn exit(handles: &ActorMainHandles) -> ! {
+    let (tx, rx) = mpsc::channel();
+    handles.inhibitors.send(InhibitMessage::ReleaseAll(tx)).unwrap();
+    let _ = rx.recv();
+    process::exit(0);
+}
+
+/// Runs the pre_shutdown hooks one after the other, core stops waiting for them at pre_shutdown_until
+fn run_pre_shutdown(state: &mut State, handles: &ActorMainHandles) {
+    let at = deadline(state.pre_shutdown_timeout);
+    state.pre_shutdown_until = Some(at);
+    handles.timer.send(TimerMessage::Set(Timer::PreShutdown, at)).unwrap();
+    let hooks = state.pre_shutdown.clone();
+    let core = handles.core.clone();
+    let shutdown = state.shutdowns;
+    thread::spawn(move || {
+        for (cmd, params) in hooks {
+            match process::Command::new(&cmd).args(&params).status() {
+                Ok(status) if !status.success() => println!("Warning: pre_shutdown hook {} exited with {}", cmd, status),
+                Err(e) => println!("Warning: could not run pre_shutdown hook {}: {}", cmd, e),
+                _ => {}
+            }
+        }
+        let _ = core.send(CoreMessage::PreShutdownDone(shutdown));
+    });
+}
+
+/// Stops the locker if configured, the shutdown delay is released once there is nothing left to do
+fn pre_shutdown_done(state: &mut State, handles: &ActorMainHandles) {
+    if state.pre_shutdown_until.take().is_some() {
+        handles.timer.send(TimerMessage::Cancel(Timer::PreShutdown)).unwrap();
+    }
+    if state.stop_locker_on_shutdown && state.locked && !state.locking {
+        // The delay is released once it is gone
+        state.stopped_for_shutdown = state.lock_reason.or(Some(LockReason::Manual));
+        unlock(state, handles, "shutdown");
+    } else {
+        handles.inhibitors.send(InhibitMessage::ReleaseShutdownDelay).unwrap();
+    }
 }
 
 fn actor_main(handles: ActorMainHandles, inbox: Receiver<CoreMessage>) {
     let mut state = State {
             locked: false,
+            autolock: true,
+            autolock_revert: None,
+            warning_to_undo: false,
+            autolock_warning: AutoLockWarning::default(),
+            autolock_pending: None,
+            grace_period: Duration::from_secs(0),
+            grace_until: None,
+            backlight: backlight::Control::new(Backlight {
+                sysfs: String::new(),
+                device: None,
+                logind: String::new(),
+                bus: BusType::System
+            }),
+            dimmed: None,
+            lid_action: None,
+            lid_revert: None,
+            lid_policy: LidPolicy {
+                battery: LidAction::Suspend,
+                ac: LidAction::Suspend,
+                docked: LidAction::Suspend
+            },
+            docked: false,
+            on_battery: false,
             inhibit_lid: false,
+            holds_delay: false,
+            announced: (true, LidAction::Suspend),
+            blank_on_lock: false,
+            blank_after: Duration::from_secs(0),
+            blank_action: BlankAction::Backlight,
+            blank_at: None,
+            blanked: false,
+            unblank_to: None,
+            sleep_on_lock: None,
+            sleep_replies: Vec::new(),
+            locker_error: None,
+            key_actions: Vec::new(),
+            keys_available: Vec::new(),
+            key_blocks: Vec::new(),
             locking: false,
             should_exit: false,
-            autolock: true
+            shutting_down: false,
+            stopped_for_shutdown: None,
+            relock: None,
+            pre_shutdown: Vec::new(),
+            pre_shutdown_timeout: Duration::from_secs(0),
+            pre_shutdown_until: None,
+            shutdowns: 0,
+            stop_locker_on_shutdown: false,
+            lock_reason: None,
+            unlock_reason: None,
+            locked_since: 0,
+            suspended_since: None,
+            locker_pid: None,
+            locker_starttime: None,
+            slept_total: power::slept_total(),
+            inhibitions: Vec::new(),
+            next_cookie: 1
     };
-    handles.inhibitors.send(InhibitMessage::CreateDelay).unwrap();
+    handles.inhibitors.send(InhibitMessage::CreateShutdownDelay).unwrap();
     {
-        let cfg = load_config();
-        apply_config(cfg.expect("Could not load configuration"), &handles);
+        let cfg = load_config().expect("Could not load configuration");
+        apply_config(cfg, &mut state, &handles);
     }
+    // The screen may still be locked from before we got restarted
+    handles.lockscreen.send(LockMessage::Adopt).unwrap();
     for message in inbox {
         println!("Received message in core: {:?}", message);
         match message {
             CoreMessage::Lock => 
                 if !(state.locked || state.locking) {
-                    handles.lockscreen.send(LockMessage::Lock).unwrap();
-                    state.locking = true;
+                    lock(&mut state, &handles, LockReason::Manual);
                 },
             CoreMessage::Unlock => 
                 if state.locked && !state.locking {
-                    handles.lockscreen.send(LockMessage::Unlock).unwrap();
-                    state.locking = true;
+                    unlock(&mut state, &handles, "manual");
+                },
+            CoreMessage::Started(pid) => {
+                // So we know what exited if it does so before it is up
+                state.locker_pid = Some(pid);
+                state.locker_starttime = procfs::starttime(pid);
+            },
+            CoreMessage::Locked(pid) => locker_up(&mut state, &handles, pid),
+            CoreMessage::SessionLock(true) =>
+                if !(state.locked || state.locking) {
+                    lock(&mut state, &handles, LockReason::Session);
                 },
-            CoreMessage::Locked => {
-                state.locked = true;
-                state.locking = false;
-                handles.inhibitors.send(InhibitMessage::ReleaseDelay).unwrap();
-            },
-            CoreMessage::Unlocked => {
-                state.locked = false;
-                state.locking = false;
-                if state.should_exit {
-                        std::process::exit(0);
+            CoreMessage::SessionLock(false) =>
+                if state.locked && !state.locking {
+                    unlock(&mut state, &handles, "session");
+                },
+            CoreMessage::Adopted(pid) => {
+                // We only ask for it while unlocked, but a Lock may have overtaken it
+                if !state.locked && !state.locking {
+                    state.lock_reason = Some(LockReason::Adopted);
+                    locker_up(&mut state, &handles, pid);
+                }
+            },
+            CoreMessage::Rescan => {
+                if !state.locked && !state.locking {
+                    handles.lockscreen.send(LockMessage::Adopt).unwrap();
+                }
+            },
+            CoreMessage::LockerFailed(pid, error) => {
+                // If we asked it to quit, a non-zero exit is expected
+                if state.unlock_reason.is_none() && is_current_locker(&state, pid) {
+                    println!("Warning: lock screen exited unexpectedly: {}", error);
+                    let locked_for = if state.locked { history::now().saturating_sub(state.locked_since) } else { 0 };
+                    record(&handles, Event::LockerCrash, &error, locked_for);
+                    state.unlock_reason = Some("crash");
+                    state.locker_error = Some(error);
+                }
+            },
+            CoreMessage::Unlocked(pid) => {
+                // Otherwise it is a locker we already gave up on, see Suspended
+                if is_current_locker(&state, pid) {
+                    locker_gone(&mut state, &handles);
                 }
-                handles.inhibitors.send(InhibitMessage::CreateDelay).unwrap();
             },
             CoreMessage::Exit => {
                 if state.locked {
-                    handles.lockscreen.send(LockMessage::Unlock).unwrap();
+                    unlock(&mut state, &handles, "exit");
                 } else {
-                    std::process::exit(0);
+                    exit(&handles);
                 }
                 state.should_exit = true;
             },
-            CoreMessage::SuspendOnLid(value) => {
-                // inhibit_lid and suspend_on_lid are opposite things
-                // hence we need a == here and not a !=
-                if value == state.inhibit_lid {
-                    state.inhibit_lid = !value;
-                    if state.inhibit_lid {
-                            handles.inhibitors.send(InhibitMessage::CreateBlock).unwrap();
-                    } else {
-                            handles.inhibitors.send(InhibitMessage::ReleaseBlock).unwrap();
+            CoreMessage::PrepareForShutdown(true) => {
+                state.shutting_down = true;
+                state.shutdowns = state.shutdowns.wrapping_add(1);
+                if state.pre_shutdown.is_empty() {
+                    pre_shutdown_done(&mut state, &handles);
+                } else {
+                    run_pre_shutdown(&mut state, &handles);
+                }
+            },
+            CoreMessage::PreShutdownDone(shutdown) => {
+                if state.shutting_down && shutdown == state.shutdowns && state.pre_shutdown_until.is_some() {
+                    pre_shutdown_done(&mut state, &handles);
+                }
+            },
+            CoreMessage::PrepareForShutdown(false) => {
+                if state.shutting_down {
+                    println!("Shutdown cancelled");
+                    state.shutting_down = false;
+                    if state.pre_shutdown_until.take().is_some() {
+                        handles.timer.send(TimerMessage::Cancel(Timer::PreShutdown)).unwrap();
+                    }
+                    handles.inhibitors.send(InhibitMessage::CreateShutdownDelay).unwrap();
+                    // Put the locker back up, once it is gone if it is still going
+                    if let Some(reason) = state.stopped_for_shutdown.take() {
+                        if state.locked {
+                            state.relock = Some(reason);
+                        } else if !state.locking {
+                            lock(&mut state, &handles, reason);
+                        }
                     }
                 }
             },
+            CoreMessage::SetLidAction(value) => {
+                if state.lid_revert.take().is_some() {
+                    handles.timer.send(TimerMessage::Cancel(Timer::LidRevert)).unwrap();
+                }
+                set_lid_action(&mut state, &handles, value);
+            },
+            CoreMessage::SetLidActionFor(value, secs) => {
+                // When extending a running timer, still go back to what was set before it
+                let previous = state.lid_revert.map_or(state.lid_action, |(v, _)| v);
+                let at = deadline(Duration::from_secs(secs));
+                state.lid_revert = Some((previous, at));
+                handles.timer.send(TimerMessage::Set(Timer::LidRevert, at)).unwrap();
+                set_lid_action(&mut state, &handles, value);
+            },
+            CoreMessage::QueryLidAction(channel) => {
+                channel.send(state.effective_lid_action()).unwrap();
+            },
+            CoreMessage::Docked(docked) => {
+                state.docked = docked;
+                update_flags(&mut state, &handles);
+            },
+            CoreMessage::OnBattery(on_battery) => {
+                state.on_battery = on_battery;
+                update_flags(&mut state, &handles);
+            },
+            CoreMessage::KeysAvailable(keys) => {
+                state.keys_available = keys;
+                update_keys(&mut state, &handles);
+            },
+            CoreMessage::KeyPressed(key) => key_pressed(&mut state, &handles, key),
+            CoreMessage::Sleep(kind, reply) => {
+                state.sleep_replies.push(reply);
+                lock_then_sleep(&mut state, &handles, kind, LockReason::Suspend);
+            },
+            CoreMessage::LidClosed(closed) => {
+                // With suspend logind acts on its own
+                if closed && state.inhibit_lid {
+                    lid_closed(&mut state, &handles);
+                }
+            },
             CoreMessage::Suspending => {
-                if !state.locked {
-                    handles.lockscreen.send(LockMessage::Lock).unwrap();
-                    state.locking = true;
+                // Whatever is left of the grace period, waking up must never unlock without a password
+                state.grace_until = None;
+                state.suspended_since = Some(history::now());
+                record(&handles, Event::Suspend, "", 0);
+                handles.api.send(ApiMessage::Suspending).unwrap();
+                if !state.locked && !state.locking && !state.inhibited(InhibitKind::SleepLock) {
+                    lock(&mut state, &handles, LockReason::Suspend);
                 }
             },
             CoreMessage::Suspended => {
-                if !state.locked {
-                    handles.lockscreen.send(LockMessage::Lock).unwrap();
-                    state.locking = true;
+                let suspended_since = state.suspended_since.take();
+                if suspended_since.is_none() {
+                    println!("Warning: resumed without having seen the machine go to sleep");
+                }
+                // The kernel knows how long we slept even if we missed going to sleep
+                let total = power::slept_total();
+                let slept = match (total, state.slept_total) {
+                    (Some(now), Some(before)) => now.saturating_sub(before),
+                    _ => suspended_since.map_or(0, |t| history::now().saturating_sub(t))
+                };
+                state.slept_total = total;
+                println!("Resumed after sleeping for {}s", slept);
+                record(&handles, Event::Resume, "", slept);
+                handles.api.send(ApiMessage::Resumed(slept)).unwrap();
+                // An autolock off --for may have run out while asleep
+                handles.timer.send(TimerMessage::Resumed).unwrap();
+                if state.locked && !state.locking {
+                    let alive = match (state.locker_pid, state.locker_starttime) {
+                        // A locker we started lives on in its process group
+                        (Some(pid), Some(starttime)) => procfs::is_alive(pid, starttime) || procfs::group_alive(pid),
+                        // Could not read its start time when it started, trust it
+                        (Some(_), None) => true,
+                        _ => false
+                    };
+                    if !alive {
+                        println!("Warning: lock screen is gone after resume, locking again");
+                        record(&handles, Event::LockerCrash, "gone after resume", history::now().saturating_sub(state.locked_since));
+                        state.unlock_reason = Some("crash");
+                        state.relock = Some(LockReason::Suspend);
+                        locker_gone(&mut state, &handles);
+                    } else {
+                        // Resuming turns the display on
+                        unblank(&mut state);
+                        schedule_blank(&mut state, &handles);
+                    }
+                } else if !state.locked && !state.locking && !state.inhibited(InhibitKind::SleepLock) {
+                    lock(&mut state, &handles, LockReason::Suspend);
                 }
             },
             CoreMessage::QueryFlag(flag, channel) => {
                 channel.send(match flag {
-                    CoreFlag::SuspendOnLid => !state.inhibit_lid,
+                    CoreFlag::SuspendOnLid => state.effective_lid_action() == LidAction::Suspend,
                     //CoreFlag::Locking => state.locking,
                     //CoreFlag::Locked => state.locked,
-                    CoreFlag::AutoLock => state.autolock
+                    CoreFlag::AutoLock => state.effective_autolock()
+                }).unwrap();
+            },
+            CoreMessage::QueryStatus(channel) => {
+                let (tx, rx) = mpsc::channel();
+                handles.inhibitors.send(InhibitMessage::Query(tx)).unwrap();
+                channel.send(Status {
+                    locked: state.locked,
+                    locking: state.locking,
+                    locked_since: state.locked_since,
+                    lock_reason: state.lock_reason,
+                    autolock: state.effective_autolock(),
+                    autolock_revert_in: remaining(state.autolock_revert),
+                    lid_action: state.effective_lid_action(),
+                    lid_action_auto: state.lid_action.is_none(),
+                    docked: state.docked,
+                    on_battery: state.on_battery,
+                    lidaction_revert_in: remaining(state.lid_revert),
+                    inhibitors: rx.recv().unwrap(),
+                    inhibitions: state.inhibitions.iter().map(|i| {
+                        format!("{} #{} by {} ({}): {}", i.kind.name(), i.cookie, i.who, i.owner, i.why)
+                    }).collect(),
+                    locker_pid: state.locker_pid,
+                    config_path: config_path().to_string_lossy().into_owned()
                 }).unwrap();
             },
+            CoreMessage::QueryHistory(since, channel) => {
+                handles.history.send(HistoryMessage::Query(since, channel)).unwrap();
+            },
             CoreMessage::AutoLock => {
-                if !state.locked && !state.locking && state.autolock {
-                    handles.lockscreen.send(LockMessage::Lock).unwrap();
+                if !state.locked && !state.locking && state.effective_autolock() && state.autolock_pending.is_none() {
+                    if state.autolock_warning.duration > Duration::from_secs(0) {
+                        warn_autolock(&mut state, &handles);
+                    } else {
+                        lock(&mut state, &handles, LockReason::AutoLock);
+                    }
+                }
+            },
+            CoreMessage::Activity => {
+                let in_grace = state.grace_until.map_or(false, |t| timer::now() < t);
+                if state.locked && !state.locking && in_grace {
+                    println!("Activity right after autolock, unlocking");
+                    state.grace_until = None;
+                    unlock(&mut state, &handles, "grace");
+                } else if end_autolock_warning(&mut state, &handles) {
+                    println!("Activity, not locking after all");
+                    restore_backlight(&mut state);
+                    if let Some((ref cmd, ref params)) = state.autolock_warning.cancel_command {
+                        spawn_command(cmd, params);
+                    }
+                } else if state.locked && !state.locking {
+                    // Show the lock screen again, and start over counting to blank_after
+                    unblank(&mut state);
+                    schedule_blank(&mut state, &handles);
                 }
             },
             CoreMessage::SetAutoLock(value) => {
-                state.autolock = value;
+                if state.autolock_revert.take().is_some() {
+                    handles.timer.send(TimerMessage::Cancel(Timer::AutoLockRevert)).unwrap();
+                }
+                set_autolock(&mut state, &handles, value);
+            },
+            CoreMessage::SetAutoLockFor(value, secs) => {
+                let previous = state.autolock_revert.map_or(state.autolock, |(v, _)| v);
+                let at = deadline(Duration::from_secs(secs));
+                state.autolock_revert = Some((previous, at));
+                handles.timer.send(TimerMessage::Set(Timer::AutoLockRevert, at)).unwrap();
+                set_autolock(&mut state, &handles, value);
+            },
+            CoreMessage::TimerExpired(timer) => {
+                // The timer may have been moved or cancelled while this message was queued
+                let now = timer::now();
+                match timer {
+                    Timer::AutoLockRevert => match state.autolock_revert {
+                        Some((value, at)) if at <= now => {
+                            state.autolock_revert = None;
+                            println!("Time is up, setting autolock back to {}", value);
+                            set_autolock(&mut state, &handles, value);
+                        },
+                        _ => {}
+                    },
+                    Timer::LidRevert => match state.lid_revert {
+                        Some((value, at)) if at <= now => {
+                            state.lid_revert = None;
+                            println!("Time is up, setting lid action back to {}", value.map_or("auto", |a| a.name()));
+                            set_lid_action(&mut state, &handles, value);
+                        },
+                        _ => {}
+                    },
+                    Timer::Blank => match state.blank_at {
+                        Some(at) if at <= now && state.locked => blank(&mut state),
+                        _ => {}
+                    },
+                    Timer::PreShutdown => match state.pre_shutdown_until {
+                        Some(at) if at <= now => {
+                            println!("Warning: pre_shutdown hooks did not finish in time, going on with the shutdown");
+                            pre_shutdown_done(&mut state, &handles);
+                        },
+                        _ => {}
+                    },
+                    Timer::AutoLockWarning => match state.autolock_pending {
+                        Some(at) if at <= now => {
+                            end_autolock_warning(&mut state, &handles);
+                            // Autolock may have been turned off or inhibited in the meantime
+                            if !state.locked && !state.locking && state.effective_autolock() {
+                                state.warning_to_undo = true;
+                                lock(&mut state, &handles, LockReason::AutoLock);
+                            } else {
+                                restore_backlight(&mut state);
+                            }
+                        },
+                        _ => {}
+                    }
+                }
+            },
+            CoreMessage::Inhibit(mut inhibition, channel) => {
+                inhibition.cookie = state.next_cookie;
+                state.next_cookie += 1;
+                println!("{} ({}) inhibits {}: {}", inhibition.who, inhibition.owner, inhibition.kind.name(), inhibition.why);
+                channel.send(inhibition.cookie).unwrap();
+                state.inhibitions.push(inhibition);
+                update_flags(&mut state, &handles);
+            },
+            CoreMessage::Release(owner, cookie, channel) => {
+                let before = state.inhibitions.len();
+                // Only the client holding it can release an inhibition
+                state.inhibitions.retain(|i| !(i.cookie == cookie && i.owner == owner));
+                channel.send(state.inhibitions.len() != before).unwrap();
+                update_flags(&mut state, &handles);
+            },
+            CoreMessage::ListInhibitions(channel) => {
+                channel.send(state.inhibitions.clone()).unwrap();
+            },
+            CoreMessage::NameLost(name) => {
+                let before = state.inhibitions.len();
+                state.inhibitions.retain(|i| i.owner != name);
+                if state.inhibitions.len() != before {
+                    println!("Released inhibitions of {}", name);
+                    update_flags(&mut state, &handles);
+                }
             },
             CoreMessage::ReloadConfig => {
                 let config = match load_config() {
@@ -250,7 +1148,9 @@ fn actor_main(handles: ActorMainHandles, inbox: Receiver<CoreMessage>) {
                         continue;
                     }
                 };
-                apply_config(config, &handles);
+                apply_config(config, &mut state, &handles);
+                record(&handles, Event::ConfigReload, "", 0);
+                handles.api.send(ApiMessage::ConfigReloaded).unwrap();
             }
 
         }
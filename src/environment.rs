@@ -0,0 +1,88 @@
+// Puts together the environment of the lock command from the environment block of the configuration.
+
+use dbus::{Connection, BusType, MessageItem, Props};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::process::Command;
+
+use msg::LockEnvironment;
+
+/// The environment of the lock command. Everything is read again every time, so changes to
+/// the systemd environment or the env file apply on the next lock.
+pub fn assemble(env: &LockEnvironment) -> HashMap<OsString, OsString> {
+    let mut vars: HashMap<OsString, OsString> = if env.clear { HashMap::new() } else { env::vars_os().collect() };
+    if let Some(ref names) = env.import_systemd {
+        for (name, value) in systemd_environment() {
+            if names.is_empty() || names.contains(&name) {
+                vars.insert(name.into(), value.into());
+            }
+        }
+    }
+    if let Some(ref path) = env.env_file {
+        for (name, value) in env_file(path) {
+            vars.insert(name.into(), value.into());
+        }
+    }
+    for &(ref name, ref value) in &env.vars {
+        vars.insert(name.into(), value.into());
+    }
+    vars
+}
+
+/// Gives command the environment put together by assemble and the working directory
+pub fn apply(command: &mut Command, vars: &HashMap<OsString, OsString>, env: &LockEnvironment) {
+    command.env_clear().envs(vars);
+    if let Some(ref dir) = env.working_dir {
+        command.current_dir(dir);
+    }
+}
+
+// What systemctl --user import-environment and dbus-update-activation-environment set
+fn systemd_environment() -> Vec<(String, String)> {
+    let con = match Connection::get_private(BusType::Session) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Warning: could not import the systemd environment: {:?}", e);
+            return Vec::new();
+        }
+    };
+    let props = Props::new(&con, "org.freedesktop.systemd1", "/org/freedesktop/systemd1", "org.freedesktop.systemd1.Manager", 2000);
+    match props.get("Environment") {
+        Ok(MessageItem::Array(items, _)) => items.iter().filter_map(|i| match *i {
+            MessageItem::Str(ref s) => split(s),
+            _ => None
+        }).collect(),
+        Ok(_) => Vec::new(),
+        Err(e) => {
+            println!("Warning: could not import the systemd environment: {:?}", e);
+            Vec::new()
+        }
+    }
+}
+
+// NAME=value lines. Empty lines, comments, export and quotes around the value are allowed, like in shell scripts.
+fn env_file(path: &str) -> Vec<(String, String)> {
+    let mut s = String::new();
+    if let Err(e) = File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+        println!("Warning: could not read environment file {}: {}", path, e);
+        return Vec::new();
+    }
+    s.lines().map(|l| l.trim()).filter(|l| !l.is_empty() && !l.starts_with('#')).filter_map(|l| {
+        let l = if l.starts_with("export ") { l[7..].trim_start() } else { l };
+        split(l).map(|(name, value)| {
+            let quoted = value.len() >= 2 && (value.starts_with('"') && value.ends_with('"') || value.starts_with('\'') && value.ends_with('\''));
+            let value = if quoted { value[1..value.len() - 1].to_string() } else { value };
+            (name, value)
+        })
+    }).collect()
+}
+
+fn split(var: &str) -> Option<(String, String)> {
+    match var.find('=') {
+        Some(i) if i > 0 => Some((var[..i].to_string(), var[i + 1..].to_string())),
+        _ => None
+    }
+}
@@ -0,0 +1,218 @@
+use std::{env, fs, path};
+use std::io::{BufRead, BufReader, Write, Result as IOResult};
+use std::path::PathBuf;
+use std::sync::mpsc::{Sender, Receiver};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Also used by lockctl, which reads the files itself when lockd is not running. Only json may be used here.
+use json;
+
+pub enum HistoryMessage {
+    Record(Entry),
+    Query(u64, Sender<Vec<Entry>>),
+    SetRotation(u64, u32)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Locking,
+    Locked,
+    Unlocked,
+    Suspend,
+    Resume,
+    LockerCrash,
+    ConfigReload
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::Locking => "locking",
+            Event::Locked => "locked",
+            Event::Unlocked => "unlocked",
+            Event::Suspend => "suspend",
+            Event::Resume => "resume",
+            Event::LockerCrash => "locker_crash",
+            Event::ConfigReload => "config_reload"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Event> {
+        match name {
+            "locking" => Some(Event::Locking),
+            "locked" => Some(Event::Locked),
+            "unlocked" => Some(Event::Unlocked),
+            "suspend" => Some(Event::Suspend),
+            "resume" => Some(Event::Resume),
+            "locker_crash" => Some(Event::LockerCrash),
+            "config_reload" => Some(Event::ConfigReload),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Unix timestamp in seconds
+    pub time: u64,
+    pub event: Event,
+    pub reason: String,
+    /// How long the state we just left lasted in seconds, 0 if not applicable
+    pub duration: u64
+}
+
+impl Entry {
+    pub fn new(event: Event, reason: &str, duration: u64) -> Entry {
+        Entry {
+            time: now(),
+            event: event,
+            reason: reason.to_string(),
+            duration: duration
+        }
+    }
+
+    fn to_json(&self) -> String {
+        format!("{{\"time\":{},\"event\":{},\"reason\":{},\"duration\":{}}}",
+                self.time, json::escape(self.event.name()), json::escape(&self.reason), self.duration)
+    }
+
+    fn from_json(line: &str) -> Option<Entry> {
+        let obj = match json::parse_object(line) {
+            Some(o) => o,
+            None => return None
+        };
+        let time = json::get(&obj, "time").and_then(|v| v.as_u64());
+        let event = json::get(&obj, "event").and_then(|v| v.as_str()).and_then(Event::from_name);
+        match (time, event) {
+            (Some(time), Some(event)) => Some(Entry {
+                time: time,
+                event: event,
+                reason: json::get(&obj, "reason").and_then(|v| v.as_str()).unwrap_or("").to_string(),
+                duration: json::get(&obj, "duration").and_then(|v| v.as_u64()).unwrap_or(0)
+            }),
+            _ => None
+        }
+    }
+}
+
+pub fn now() -> u64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs(),
+        Err(_) => 0
+    }
+}
+
+pub fn actor_history(inbox: Receiver<HistoryMessage>) {
+    let mut log = HistoryLog {
+        path: history_path(),
+        max_size: 1024 * 1024,
+        keep: 3
+    };
+    for message in inbox {
+        match message {
+            HistoryMessage::Record(entry) => {
+                if let Err(e) = log.append(&entry) {
+                    println!("Warning: could not write history to {}: {}", log.path.display(), e);
+                }
+            },
+            HistoryMessage::Query(since, channel) => {
+                channel.send(log.read(since)).unwrap();
+            },
+            HistoryMessage::SetRotation(max_size, keep) => {
+                log.max_size = max_size;
+                log.keep = keep;
+            }
+        }
+    }
+}
+
+struct HistoryLog {
+    path: PathBuf,
+    max_size: u64,
+    keep: u32
+}
+
+impl HistoryLog {
+    fn append(&self, entry: &Entry) -> IOResult<()> {
+        let line = entry.to_json() + "\n";
+        if let Some(dir) = self.path.parent() {
+            try!(fs::create_dir_all(dir));
+        }
+        if let Ok(md) = fs::metadata(&self.path) {
+            if md.len() + line.len() as u64 > self.max_size {
+                try!(self.rotate());
+            }
+        }
+        let mut f = try!(fs::OpenOptions::new().create(true).append(true).open(&self.path));
+        f.write_all(line.as_bytes())
+    }
+
+    fn rotate(&self) -> IOResult<()> {
+        if self.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        // history.jsonl.(keep-1) -> history.jsonl.keep, ..., history.jsonl -> history.jsonl.1
+        // The oldest one simply gets overwritten
+        for i in (1..self.keep).rev() {
+            let from = self.rotated(i);
+            if fs::metadata(&from).is_ok() {
+                try!(fs::rename(&from, self.rotated(i + 1)));
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))
+    }
+
+    fn rotated(&self, i: u32) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", i));
+        PathBuf::from(name)
+    }
+
+    fn read(&self, since: u64) -> Vec<Entry> {
+        let mut files: Vec<PathBuf> = (1..self.keep + 1).rev().map(|i| self.rotated(i)).collect();
+        files.push(self.path.clone());
+        let mut ret = Vec::new();
+        for file in files {
+            let f = match fs::File::open(&file) {
+                Ok(f) => f,
+                Err(_) => continue
+            };
+            // A read error would come back on every call
+            for line in BufReader::new(f).lines().map_while(Result::ok) {
+                match Entry::from_json(&line) {
+                    Some(ref entry) if entry.time >= since => ret.push(entry.clone()),
+                    _ => {}
+                }
+            }
+        }
+        ret
+    }
+}
+
+/// Entries since a time from all files found, for lockctl when there is no lockd to ask
+#[allow(dead_code)]
+pub fn read(since: u64) -> Vec<Entry> {
+    let mut log = HistoryLog {
+        path: history_path(),
+        max_size: 0,
+        keep: 0
+    };
+    while fs::metadata(log.rotated(log.keep + 1)).is_ok() {
+        log.keep += 1;
+    }
+    log.read(since)
+}
+
+fn history_path() -> PathBuf {
+    let base = match env::var("XDG_STATE_HOME") {
+        Ok(ref dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => match env::var("HOME") {
+            Ok(home) => path::Path::new(&home).join(".local/state"),
+            Err(e) => {
+                println!("Warning: Could not get $HOME: {}, writing history to /tmp", e);
+                PathBuf::from("/tmp")
+            }
+        }
+    };
+    base.join("lockd").join("history.jsonl")
+}
@@ -1,18 +1,23 @@
 use dbus::{OwnedFd, Message, Connection, BusType};
 use std::sync::{Mutex, Arc};
+use std::collections::HashMap;
 use std::sync::mpsc::{Sender, Receiver};
-use msg::{InhibitMessage, CoreMessage};
+use msg::{InhibitMessage, CoreMessage, Key};
 
 pub fn actor_inhibit(core: Sender<CoreMessage>, cmd: Receiver<InhibitMessage>) {
   InhibitData {
     block: Arc::new(Mutex::new(None)),
     delay: Arc::new(Mutex::new(None)),
+    shutdown: Arc::new(Mutex::new(None)),
+    keys: Arc::new(Mutex::new(HashMap::new())),
   }.actor_run(core, cmd);
 }
 
 struct InhibitData {
   block: Arc<Mutex<Option<OwnedFd>>>,
-  delay: Arc<Mutex<Option<OwnedFd>>>
+  delay: Arc<Mutex<Option<OwnedFd>>>,
+  shutdown: Arc<Mutex<Option<OwnedFd>>>,
+  keys: Arc<Mutex<HashMap<Key, OwnedFd>>>
 }
 
 impl InhibitData {
@@ -22,7 +27,7 @@ impl InhibitData {
       match msg {
         InhibitMessage::CreateBlock => {
           let m = InhibitData::new_msg()
-              .append3("handle-lid-switch", "lockd", "Lid Switch Disabled")
+              .append3("handle-lid-switch", "lockd", "lockd handles the lid switch")
               .append1("block");
           let r = connection.send_with_reply_and_block(m, 2000).unwrap();
           let mut lock = self.block.lock().unwrap();
@@ -36,6 +41,36 @@ impl InhibitData {
           let mut lock = self.delay.lock().unwrap();
           *lock = Some(r.get1().unwrap());
         },
+        InhibitMessage::CreateShutdownDelay => {
+          let m = InhibitData::new_msg()
+              .append3("shutdown", "lockd", "lockd wants to stop the lock screen before shutdown")
+              .append1("delay");
+          let r = connection.send_with_reply_and_block(m, 2000).unwrap();
+          let mut lock = self.shutdown.lock().unwrap();
+          *lock = Some(r.get1().unwrap());
+        },
+        InhibitMessage::ReleaseShutdownDelay => {
+          *self.shutdown.lock().unwrap() = None;
+        },
+        InhibitMessage::ReleaseAll(done) => {
+          *self.block.lock().unwrap() = None;
+          *self.delay.lock().unwrap() = None;
+          *self.shutdown.lock().unwrap() = None;
+          self.keys.lock().unwrap().clear();
+          done.send(()).unwrap();
+        },
+        InhibitMessage::CreateKeyBlock(key) => {
+          let m = InhibitData::new_msg()
+              .append3(key.inhibitor(), "lockd", "lockd handles this key")
+              .append1("block");
+          let r = connection.send_with_reply_and_block(m, 2000).unwrap();
+          let mut lock = self.keys.lock().unwrap();
+          lock.insert(key, r.get1().unwrap());
+        },
+        InhibitMessage::ReleaseKeyBlock(key) => {
+          let mut lock = self.keys.lock().unwrap();
+          lock.remove(&key); // lock gets dropped here
+        },
         InhibitMessage::ReleaseBlock => {
           let mut lock = self.block.lock().unwrap();
           *lock = None; // lock gets dropped here
@@ -43,6 +78,22 @@ impl InhibitData {
         InhibitMessage::ReleaseDelay => {
           let mut lock = self.delay.lock().unwrap();
           *lock = None; // lock gets dropped here
+        },
+        InhibitMessage::Query(channel) => {
+          let mut held = Vec::new();
+          if self.delay.lock().unwrap().is_some() {
+            held.push(format!("sleep (delay)"));
+          }
+          if self.shutdown.lock().unwrap().is_some() {
+            held.push(format!("shutdown (delay)"));
+          }
+          if self.block.lock().unwrap().is_some() {
+            held.push(format!("handle-lid-switch (block)"));
+          }
+          for key in self.keys.lock().unwrap().keys() {
+            held.push(format!("{} (block)", key.inhibitor()));
+          }
+          channel.send(held).unwrap();
         }
       }
     }
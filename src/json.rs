@@ -0,0 +1,159 @@
+// Just enough JSON for the files lockd writes itself and for lockctl output.
+// Only flat objects with string, number and boolean values are supported.
+
+use std::char;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Num(u64),
+    Bool(bool),
+    Null
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Str(ref s) => Some(s),
+            _ => None
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Num(n) => Some(n),
+            _ => None
+        }
+    }
+}
+
+pub fn escape(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c)
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+/// Parses a single flat JSON object. Returns None on anything we do not understand.
+pub fn parse_object(s: &str) -> Option<Vec<(String, Value)>> {
+    let mut it = s.trim().chars().peekable();
+    let mut ret = Vec::new();
+    if it.next() != Some('{') {
+        return None;
+    }
+    skip_ws(&mut it);
+    if it.peek() == Some(&'}') {
+        it.next();
+        return Some(ret);
+    }
+    loop {
+        skip_ws(&mut it);
+        if it.next() != Some('"') {
+            return None;
+        }
+        let key = match parse_string(&mut it) {
+            Some(k) => k,
+            None => return None
+        };
+        skip_ws(&mut it);
+        if it.next() != Some(':') {
+            return None;
+        }
+        skip_ws(&mut it);
+        let value = match it.peek().cloned() {
+            Some('"') => {
+                it.next();
+                match parse_string(&mut it) {
+                    Some(s) => Value::Str(s),
+                    None => return None
+                }
+            },
+            Some(c) if c.is_digit(10) => {
+                let mut n: u64 = 0;
+                while let Some(d) = it.peek().and_then(|c| c.to_digit(10)) {
+                    n = n.saturating_mul(10).saturating_add(d as u64);
+                    it.next();
+                }
+                Value::Num(n)
+            },
+            Some(_) => {
+                let mut word = String::new();
+                while let Some(&c) = it.peek() {
+                    if !c.is_alphabetic() {
+                        break;
+                    }
+                    word.push(c);
+                    it.next();
+                }
+                match &*word {
+                    "true" => Value::Bool(true),
+                    "false" => Value::Bool(false),
+                    "null" => Value::Null,
+                    _ => return None
+                }
+            },
+            None => return None
+        };
+        ret.push((key, value));
+        skip_ws(&mut it);
+        match it.next() {
+            Some(',') => {},
+            Some('}') => return Some(ret),
+            _ => return None
+        }
+    }
+}
+
+pub fn get<'a>(obj: &'a [(String, Value)], key: &str) -> Option<&'a Value> {
+    obj.iter().find(|&&(ref k, _)| k == key).map(|&(_, ref v)| v)
+}
+
+fn skip_ws<I: Iterator<Item=char>>(it: &mut ::std::iter::Peekable<I>) {
+    while let Some(&c) = it.peek() {
+        if !c.is_whitespace() {
+            break;
+        }
+        it.next();
+    }
+}
+
+// Expects the opening quote to be consumed already
+fn parse_string<I: Iterator<Item=char>>(it: &mut I) -> Option<String> {
+    let mut ret = String::new();
+    loop {
+        match it.next() {
+            Some('"') => return Some(ret),
+            Some('\\') => match it.next() {
+                Some('"') => ret.push('"'),
+                Some('\\') => ret.push('\\'),
+                Some('/') => ret.push('/'),
+                Some('b') => ret.push('\u{8}'),
+                Some('f') => ret.push('\u{c}'),
+                Some('n') => ret.push('\n'),
+                Some('r') => ret.push('\r'),
+                Some('t') => ret.push('\t'),
+                Some('u') => {
+                    let hex: String = it.take(4).collect();
+                    match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                        Some(c) => ret.push(c),
+                        None => ret.push('\u{fffd}')
+                    }
+                },
+                _ => return None
+            },
+            Some(c) => ret.push(c),
+            None => return None
+        }
+    }
+}
@@ -0,0 +1,149 @@
+// Reads the power, suspend and hibernate keys from evdev, logind does not tell anyone about them.
+
+use libc;
+use std::fs;
+use std::io::Read;
+use std::mem;
+use std::os::unix::io::AsRawFd;
+use std::sync::mpsc::{Sender, Receiver, TryRecvError};
+
+use msg::{KeysMessage, CoreMessage, Key};
+
+// From linux/input-event-codes.h
+const EV_KEY: u16 = 1;
+const KEYS: [(u16, Key); 3] = [(116, Key::Power), (142, Key::Suspend), (205, Key::Hibernate)];
+
+struct Device {
+    path: String,
+    file: fs::File
+}
+
+pub fn actor_keys(core: Sender<CoreMessage>, inbox: Receiver<KeysMessage>) {
+    let mut devices: Vec<Device> = Vec::new();
+    // Until the first SetDevice there is nothing to read, afterwards check the inbox twice a second
+    let mut message = inbox.recv().ok();
+    loop {
+        match message {
+            Some(KeysMessage::SetDevice(device)) => {
+                let paths = match device {
+                    Some(path) => vec![path],
+                    None => find_devices()
+                };
+                devices = paths.into_iter().filter_map(|path| match fs::File::open(&path) {
+                    Ok(file) => Some(Device { path: path, file: file }),
+                    Err(e) => {
+                        println!("Warning: could not open input device {}: {}", path, e);
+                        None
+                    }
+                }).collect();
+                let mut available = Vec::new();
+                for device in &devices {
+                    for key in supported_keys(&device.path) {
+                        if !available.contains(&key) {
+                            available.push(key);
+                        }
+                    }
+                }
+                core.send(CoreMessage::KeysAvailable(available)).unwrap();
+            },
+            Some(KeysMessage::Close) => {
+                devices.clear();
+                core.send(CoreMessage::KeysAvailable(Vec::new())).unwrap();
+            },
+            None => {}
+        }
+
+        let mut fds: Vec<libc::pollfd> = devices.iter().map(|d| libc::pollfd {
+            fd: d.file.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0
+        }).collect();
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 500) };
+        if ready > 0 {
+            let mut gone = Vec::new();
+            for (i, fd) in fds.iter().enumerate() {
+                if fd.revents == 0 {
+                    continue;
+                }
+                if !read_events(&mut devices[i].file, &core) {
+                    println!("Warning: input device {} went away", devices[i].path);
+                    gone.push(i);
+                }
+            }
+            for i in gone.into_iter().rev() {
+                devices.remove(i);
+            }
+        }
+
+        message = match inbox.try_recv() {
+            Ok(m) => Some(m),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => return
+        };
+    }
+}
+
+/// Reads the pending events of a device, false if it cannot be read anymore
+fn read_events(file: &mut fs::File, core: &Sender<CoreMessage>) -> bool {
+    // struct input_event: struct timeval, __u16 type, __u16 code, __s32 value
+    let offset = mem::size_of::<libc::timeval>();
+    let size = offset + 8;
+    let mut buf = vec![0u8; size * 16];
+    let len = match file.read(&mut buf) {
+        Ok(0) | Err(_) => return false,
+        Ok(len) => len
+    };
+    for event in buf[..len].chunks(size).filter(|e| e.len() == size) {
+        let kind = u16::from_ne_bytes([event[offset], event[offset + 1]]);
+        let code = u16::from_ne_bytes([event[offset + 2], event[offset + 3]]);
+        let value = i32::from_ne_bytes([event[offset + 4], event[offset + 5], event[offset + 6], event[offset + 7]]);
+        // 1 is the key going down, 2 is autorepeat
+        if kind != EV_KEY || value != 1 {
+            continue;
+        }
+        if let Some(&(_, key)) = KEYS.iter().find(|&&(c, _)| c == code) {
+            core.send(CoreMessage::KeyPressed(key)).unwrap();
+        }
+    }
+    true
+}
+
+// sysfs tells us about the keys of every device without needing to open it
+fn capabilities(event: &str) -> Vec<u64> {
+    let mut s = String::new();
+    let path = format!("/sys/class/input/{}/device/capabilities/key", event);
+    if fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut s)).is_err() {
+        return Vec::new();
+    }
+    // Hex words, most significant first
+    s.split_whitespace().rev().map(|w| u64::from_str_radix(w, 16).unwrap_or(0)).collect()
+}
+
+fn supported_keys(path: &str) -> Vec<Key> {
+    let event = match fs::canonicalize(path).ok().and_then(|p| p.file_name().map(|n| n.to_string_lossy().into_owned())) {
+        Some(e) => e,
+        None => return Vec::new()
+    };
+    let words = capabilities(&event);
+    let bits = mem::size_of::<libc::c_long>() * 8;
+    KEYS.iter().filter(|&&(code, _)| {
+        let code = code as usize;
+        words.get(code / bits).map_or(false, |w| w & (1 << (code % bits)) != 0)
+    }).map(|&(_, key)| key).collect()
+}
+
+/// All event devices that have one of the keys we care about
+fn find_devices() -> Vec<String> {
+    let entries = match fs::read_dir("/sys/class/input") {
+        Ok(e) => e,
+        Err(_) => return Vec::new()
+    };
+    let mut ret: Vec<String> = entries.filter_map(|e| e.ok())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.starts_with("event"))
+        .map(|name| format!("/dev/input/{}", name))
+        .filter(|path| !supported_keys(path).is_empty())
+        .collect();
+    ret.sort();
+    ret
+}
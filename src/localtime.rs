@@ -0,0 +1,59 @@
+// Local time helpers on top of libc, std does not know about time zones.
+
+use libc;
+use std::ffi::CString;
+use std::mem;
+
+extern "C" {
+    // Not exported by the libc crate version we use
+    fn strftime(s: *mut libc::c_char, max: libc::size_t, format: *const libc::c_char, tm: *const libc::tm) -> libc::size_t;
+}
+
+fn local(ts: u64) -> libc::tm {
+    let t = ts as libc::time_t;
+    unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        libc::localtime_r(&t, &mut tm);
+        tm
+    }
+}
+
+/// Formats a unix timestamp in local time using strftime syntax
+pub fn format(ts: u64, fmt: &str) -> String {
+    let tm = local(ts);
+    let fmt = match CString::new(fmt) {
+        Ok(f) => f,
+        Err(_) => return String::new()
+    };
+    let mut buf = vec![0u8; 256];
+    let len = unsafe {
+        strftime(buf.as_mut_ptr() as *mut libc::c_char, buf.len(), fmt.as_ptr(), &tm)
+    };
+    buf.truncate(len);
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+// Timestamp of hour:minute local time on the day ts is in
+fn at_time(ts: u64, hour: u32, minute: u32) -> u64 {
+    let mut tm = local(ts);
+    tm.tm_sec = 0;
+    tm.tm_min = minute as libc::c_int;
+    tm.tm_hour = hour as libc::c_int;
+    tm.tm_isdst = -1;
+    unsafe { libc::mktime(&mut tm) as u64 }
+}
+
+/// Returns the timestamp of the local midnight starting the day ts is in
+pub fn start_of_day(ts: u64) -> u64 {
+    at_time(ts, 0, 0)
+}
+
+/// Seconds from ts until the next time the local clock shows hour:minute
+pub fn until(ts: u64, hour: u32, minute: u32) -> u64 {
+    let mut at = at_time(ts, hour, minute);
+    if at <= ts {
+        // Already past that today
+        at = at_time(ts + 24 * 60 * 60, hour, minute);
+    }
+    at - ts
+}
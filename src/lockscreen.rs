@@ -1,47 +1,360 @@
-use std::process::{Command, Child};
-use std::sync::mpsc::{Sender, Receiver};
+use std::collections::VecDeque;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::{self, Command, Child, ExitStatus, Stdio};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::CommandExt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Sender, Receiver, TryRecvError};
+use std::os::unix::io::RawFd;
 use std::thread;
+use std::time::{Duration, Instant};
 
-use msg::{LockMessage, CoreMessage};
+use libc;
+use msg::{LockMessage, CoreMessage, LockEnvironment, LockCommand, LockerTable};
+use template::Template;
+use environment;
+use procfs;
+
+// Same on every architecture but alpha
+const SYS_PIDFD_OPEN: libc::c_long = 434;
+const SYS_PIDFD_SEND_SIGNAL: libc::c_long = 424;
+// Lines of output kept for the error when a locker fails
+const OUTPUT_LINES: usize = 10;
+
+// Makes the names of output files unique
+static OUTPUT_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// What a locker we started printed. It writes to a deleted file instead of a pipe, so it does not get
+/// SIGPIPE and unlock the screen when we exit or crash.
+struct Output {
+    file: File,
+    prefix: String,
+    // Anything after the last complete line
+    partial: Vec<u8>,
+    lines: VecDeque<String>
+}
+
+impl Output {
+    /// Copies new lines to our log and keeps the last ones
+    fn read(&mut self) {
+        let mut buf = Vec::new();
+        if let Err(e) = self.file.read_to_end(&mut buf) {
+            println!("Warning: could not read the output of {}: {}", self.prefix, e);
+        }
+        self.partial.extend(buf);
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..end + 1).collect();
+            self.line(String::from_utf8_lossy(&line).trim_end().to_string());
+        }
+    }
+
+    /// Reads what is left once the locker exited, including a last line without a newline
+    fn finish(&mut self) {
+        self.read();
+        if !self.partial.is_empty() {
+            let line = String::from_utf8_lossy(&self.partial).trim_end().to_string();
+            self.partial.clear();
+            self.line(line);
+        }
+    }
+
+    fn line(&mut self, line: String) {
+        println!("{}: {}", self.prefix, line);
+        if self.lines.len() == OUTPUT_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back(line);
+    }
+}
+
+/// A running locker. Only this actor reaps the ones it started, and it drops the Locker once nothing is left
+/// in their process group, so signals never reach a process that got the PID later.
+struct Locker {
+    pid: u32,
+    // None for adopted lockers, which are not our children and get reaped by someone else
+    child: Option<Child>,
+    // How the process we started exited, while what it started is still running
+    leader_status: Option<ExitStatus>,
+    // Overrides unlock_signal
+    unlock_signal: Option<libc::c_int>,
+    // Start time of an adopted locker, to tell it from whatever gets its PID later
+    starttime: u64,
+    // None for adopted lockers
+    output: Option<Output>,
+    // None on kernels before 5.3
+    pidfd: Option<RawFd>,
+    // When to give up on it exiting by itself after being asked to
+    kill_at: Option<Instant>,
+    // When to tell core the screen is locked, None once we did
+    ready_at: Option<Instant>
+}
+
+impl Locker {
+    fn new(child: Child, output: Option<File>, name: &str) -> Locker {
+        let mut locker = Locker::watch(child.id(), 0);
+        let prefix = format!("{}[{}]", name, child.id());
+        locker.output = output.map(|file| Output { file: file, prefix: prefix, partial: Vec::new(), lines: VecDeque::new() });
+        locker.child = Some(child);
+        locker
+    }
+
+    fn watch(pid: u32, starttime: u64) -> Locker {
+        let fd = unsafe { libc::syscall(SYS_PIDFD_OPEN, pid as libc::pid_t, 0) };
+        Locker {
+            pid: pid,
+            child: None,
+            leader_status: None,
+            unlock_signal: None,
+            starttime: starttime,
+            output: None,
+            pidfd: if fd >= 0 { Some(fd as RawFd) } else { None },
+            kill_at: None,
+            ready_at: None
+        }
+    }
+
+    /// Signals the locker and everything it started
+    fn signal(&self, signal: libc::c_int) {
+        if self.child.is_some() {
+            // The locker leads its own process group, and as long as anything is left in it the
+            // group ID cannot have been reused. Unlike pidfd_send_signal this also reaches whatever
+            // a wrapper script started.
+            unsafe { libc::kill(-(self.pid as libc::pid_t), signal) };
+        } else if let Some(fd) = self.pidfd {
+            // Whatever group an adopted locker is in may not be its own
+            unsafe { libc::syscall(SYS_PIDFD_SEND_SIGNAL, fd, signal, 0 as *const libc::c_void, 0) };
+        } else if procfs::is_alive(self.pid, self.starttime) {
+            unsafe { libc::kill(self.pid as libc::pid_t, signal) };
+        }
+    }
+
+    /// The exit status once it and everything in its process group exited, None while any of it runs.
+    /// Adopted lockers have no status we could see.
+    fn exited(&mut self) -> Option<Option<String>> {
+        let status = match self.child {
+            Some(ref mut child) => match self.leader_status {
+                Some(status) => status,
+                None => match child.try_wait() {
+                    Ok(Some(status)) => status,
+                    Ok(None) => return None,
+                    Err(e) => {
+                        println!("Warning: could not wait for lock screen: {}", e);
+                        return None;
+                    }
+                }
+            },
+            None => return if procfs::is_alive(self.pid, self.starttime) { None } else { Some(None) }
+        };
+        self.leader_status = Some(status);
+        // A locker that forks, or a wrapper that did not wait for it, still keeps the screen locked
+        if procfs::group_alive(self.pid) {
+            return None;
+        }
+        if status.success() {
+            return Some(None);
+        }
+        let output: Vec<String> = match self.output {
+            Some(ref mut output) => {
+                output.finish();
+                output.lines.iter().cloned().collect()
+            },
+            None => Vec::new()
+        };
+        if output.is_empty() {
+            Some(Some(format!("{}", status)))
+        } else {
+            Some(Some(format!("{}, last output: {}", status, output.join(" / "))))
+        }
+    }
+}
+
+impl Drop for Locker {
+    fn drop(&mut self) {
+        if let Some(fd) = self.pidfd {
+            unsafe { libc::close(fd) };
+        }
+    }
+}
 
 pub fn actor_lockscreen(core: Sender<CoreMessage>, cmd: Receiver<LockMessage>) {
-    let mut command = (format!("echo"), vec![format!("Error: did not provide correct lock command from core. This IS a bug.")]);
-    let mut pid: Option<u32> = None;
-    for message in cmd {
+    let mut table = LockerTable {
+        default: LockCommand {
+            program: format!("echo"),
+            args: vec![Template::text("Error: did not provide correct lock command from core. This IS a bug.")],
+            ready_delay: Duration::from_secs(0),
+            unlock_signal: None
+        },
+        triggers: Vec::new()
+    };
+    let mut unlock_signal = libc::SIGTERM;
+    let mut unlock_timeout = Duration::from_secs(5);
+    let mut env = LockEnvironment::default();
+    // The last one is current, older ones are only still around if core gave up on them
+    let mut lockers: Vec<Locker> = Vec::new();
+    loop {
+        let message = if lockers.is_empty() {
+            match cmd.recv() {
+                Ok(m) => m,
+                Err(_) => return
+            }
+        } else {
+            wait(&mut lockers, &core);
+            match cmd.try_recv() {
+                Ok(m) => m,
+                Err(TryRecvError::Empty) => continue,
+                Err(TryRecvError::Disconnected) => return
+            }
+        };
         match message {
-            LockMessage::Lock => {
-                let child = lock_command(&command);
-                pid = Some(child.id());
-                core.send(CoreMessage::Locked).unwrap();
-                let core_clone = core.clone();
-                thread::spawn(||{
-                    actor_lock_instance(core_clone, child);
-                });
-            },
-            LockMessage::Unlock => match pid {
-                Some(pid) => {
-                    unsafe { ::libc::kill(pid as i32, ::libc::SIGTERM) };
+            LockMessage::Lock(reason) => {
+                let command = table.command(reason);
+                let (child, output) = match lock_command(command, reason.name(), &env) {
+                    Ok(started) => started,
+                    Err(e) => {
+                        println!("Warning: could not start lock screen {}: {}", command.program, e);
+                        core.send(CoreMessage::LockerFailed(0, format!("could not start {}: {}", command.program, e))).unwrap();
+                        core.send(CoreMessage::Unlocked(0)).unwrap();
+                        continue;
+                    }
+                };
+                core.send(CoreMessage::Started(child.id())).unwrap();
+                let name = Path::new(&command.program).file_name().map_or(command.program.clone(), |n| n.to_string_lossy().into_owned());
+                let mut locker = Locker::new(child, output, &name);
+                // Sent by wait(), unless it exits before
+                locker.ready_at = Some(Instant::now() + command.ready_delay);
+                locker.unlock_signal = command.unlock_signal;
+                lockers.push(locker);
+            },
+            LockMessage::Unlock => match lockers.last_mut() {
+                Some(locker) => {
+                    locker.signal(locker.unlock_signal.unwrap_or(unlock_signal));
+                    if unlock_timeout > Duration::from_secs(0) {
+                        locker.kill_at = Some(Instant::now() + unlock_timeout);
+                    }
                 },
                 None => {}
             },
-            LockMessage::SetLockscreen(cmd, params) => {
-                command = (cmd, params);
+            LockMessage::SetLockscreen(t) => {
+                table = t;
+            },
+            LockMessage::SetUnlockPolicy(signal, timeout) => {
+                unlock_signal = signal;
+                unlock_timeout = timeout;
+            },
+            LockMessage::SetEnvironment(e) => {
+                env = e;
+            },
+            LockMessage::Adopt => if lockers.is_empty() {
+                if let Some((pid, starttime)) = procfs::find_locker(&table.programs()) {
+                    println!("Adopting lock screen {}", pid);
+                    lockers.push(Locker::watch(pid, starttime));
+                    core.send(CoreMessage::Adopted(pid)).unwrap();
+                }
             }
         }
     }
 }
 
-fn lock_command(command: &(String, Vec<String>)) -> Child {
-    let mut c = Command::new(&command.0);
-    //let c = command.1.iter().fold(c, |c, arg| c.arg(arg));
+/// Starts the locker, along with where to read what it prints
+fn lock_command(command: &LockCommand, reason: &str, env: &LockEnvironment) -> io::Result<(Child, Option<File>)> {
+    let mut c = Command::new(&command.program);
+    //let c = command.args.iter().fold(c, |c, arg| c.arg(arg));
     // FIXME: Ugly workaround until i can get fold to behave
-    for arg in &command.1 {
-        c.arg(arg);
+    // Placeholders see the environment of the locker, not ours
+    let vars = environment::assemble(env);
+    for arg in &command.args {
+        c.arg(arg.render(reason, &vars));
     }
-    c.spawn().unwrap()
+    environment::apply(&mut c, &vars, env);
+    // Logged line by line with a prefix instead of mixing with our own output
+    let output = match output_file() {
+        Ok((write, read)) => {
+            c.stdout(Stdio::from(try!(write.try_clone()))).stderr(Stdio::from(write));
+            Some(read)
+        },
+        Err(e) => {
+            println!("Warning: could not create a file for the output of {}: {}", command.program, e);
+            None
+        }
+    };
+    // Own session and process group, so unlocking can signal all of it
+    unsafe {
+        c.pre_exec(|| {
+            libc::setsid();
+            Ok(())
+        });
+    }
+    c.spawn().map(|child| (child, output))
+}
+
+/// A file to write to and one to read from, already deleted so that nothing is left behind
+fn output_file() -> io::Result<(File, File)> {
+    let dir = env::var_os("XDG_RUNTIME_DIR").map_or(env::temp_dir(), |d| Path::new(&d).to_path_buf());
+    let path = dir.join(format!("lockd-{}-{}.log", process::id(), OUTPUT_FILES.fetch_add(1, Ordering::Relaxed)));
+    let write = try!(OpenOptions::new().append(true).create_new(true).mode(0o600).open(&path));
+    let read = File::open(&path);
+    let _ = fs::remove_file(&path);
+    Ok((write, try!(read)))
 }
-    
-fn actor_lock_instance(core: Sender<CoreMessage>, mut child: Child) {
-    child.wait().unwrap();
-    core.send(CoreMessage::Unlocked).unwrap();
+
+/// Waits up to 100ms for lockers to exit and reaps the ones that did. Lockers still running at their
+/// ready_at are reported as locked.
+fn wait(lockers: &mut Vec<Locker>, core: &Sender<CoreMessage>) {
+    // Once the process we started is reaped its pidfd stays readable, so only the group is left to poll
+    let mut fds: Vec<libc::pollfd> = lockers.iter().filter(|l| l.leader_status.is_none()).filter_map(|l| l.pidfd).map(|fd| libc::pollfd {
+        fd: fd,
+        events: libc::POLLIN,
+        revents: 0
+    }).collect();
+    if fds.len() == lockers.len() {
+        // A pidfd becomes readable once the process exits
+        unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, 100) };
+    } else {
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let now = Instant::now();
+    for locker in lockers.iter_mut() {
+        if let Some(ref mut output) = locker.output {
+            output.read();
+        }
+        match locker.kill_at {
+            Some(at) if at <= now => {
+                println!("Warning: lock screen {} did not exit in time, killing it", locker.pid);
+                locker.signal(libc::SIGKILL);
+                locker.kill_at = None;
+            },
+            _ => {}
+        }
+    }
+
+    let mut i = 0;
+    while i < lockers.len() {
+        let error = match lockers[i].exited() {
+            Some(error) => error,
+            None => {
+                i += 1;
+                continue;
+            }
+        };
+        let pid = lockers.remove(i).pid;
+        if let Some(error) = error {
+            // This includes us killing it on unlock, core sorts that out
+            core.send(CoreMessage::LockerFailed(pid, error)).unwrap();
+        }
+        core.send(CoreMessage::Unlocked(pid)).unwrap();
+    }
+
+    for locker in lockers.iter_mut() {
+        match locker.ready_at {
+            Some(at) if at <= now => {
+                locker.ready_at = None;
+                core.send(CoreMessage::Locked(locker.pid)).unwrap();
+            },
+            _ => {}
+        }
+    }
 }
@@ -0,0 +1,19 @@
+// Finding our way around logind, for everything that acts on our own session.
+
+use dbus::{Connection, Message, MessageItem};
+use std::{env, process};
+
+/// Our logind session: the one XDG_SESSION_ID names, otherwise the one we run in
+pub fn session_path(con: &Connection, service: &str) -> Option<String> {
+  let m = match env::var("XDG_SESSION_ID") {
+    Ok(id) => Message::new_method_call(service, "/org/freedesktop/login1", "org.freedesktop.login1.Manager", "GetSession").unwrap().append1(id),
+    Err(_) => Message::new_method_call(service, "/org/freedesktop/login1", "org.freedesktop.login1.Manager", "GetSessionByPID").unwrap().append1(process::id())
+  };
+  match con.send_with_reply_and_block(m, 2000).ok().map(|r| r.get_items()) {
+    Some(items) => match items.get(0) {
+      Some(&MessageItem::ObjectPath(ref path)) => Some(path.to_string()),
+      _ => None
+    },
+    None => None
+  }
+}
@@ -1,33 +1,133 @@
+use dbus::BusType;
 use std::fmt;
 use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use history;
+use power::SleepKind;
+use template::Template;
 
 pub enum LockMessage {
-  Lock,
+  Lock(LockReason),
   Unlock,
-  SetLockscreen(String, Vec<String>)
+  SetLockscreen(LockerTable),
+  // Signal to ask the locker to exit and how long to wait before killing it, zero waits forever
+  SetUnlockPolicy(i32, Duration),
+  SetEnvironment(LockEnvironment),
+  // Look for a lock screen we did not start and take it over if nothing is locked
+  Adopt
 }
 
 pub enum InhibitMessage {
   CreateBlock,
   ReleaseBlock,
   CreateDelay,
-  ReleaseDelay
+  ReleaseDelay,
+  CreateKeyBlock(Key),
+  ReleaseKeyBlock(Key),
+  CreateShutdownDelay,
+  ReleaseShutdownDelay,
+  // Replies once everything is released
+  ReleaseAll(Sender<()>),
+  Query(Sender<Vec<String>>)
+}
+
+pub enum KeysMessage {
+  // Device to read key presses from, None looks for devices with the keys
+  SetDevice(Option<String>),
+  Close
+}
+
+pub enum NotifyMessage {
+  // Summary, body and how long to show it in milliseconds
+  Show(String, String, u32),
+  Close
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Timer {
+  AutoLockRevert,
+  LidRevert,
+  // End of the autolock warning
+  AutoLockWarning,
+  // Turn the display off while locked
+  Blank,
+  // Stop waiting for the pre_shutdown hooks
+  PreShutdown
+}
+
+pub enum TimerMessage {
+  // Deadline as timer::now()
+  Set(Timer, Duration),
+  Cancel(Timer),
+  // The machine woke up, deadlines may have passed while it slept
+  Resumed
+}
+
+/// Signals to be emitted on the D-Bus API
+pub enum ApiMessage {
+  Locked(LockReason),
+  Unlocked(String),
+  AutoLockChanged(bool),
+  LidActionChanged(LidAction),
+  Suspending,
+  Resumed(u64),
+  ConfigReloaded
 }
 
 //#[derive(Debug)]
 pub enum CoreMessage {
   Lock,
   Unlock,
-  Locked,
-  Unlocked,
+  // The lock screen was started, but may not have grabbed the screen yet
+  Started(u32),
+  Locked(u32),
+  // Lock or Unlock signal of our logind session
+  SessionLock(bool),
+  // A lock screen somebody else started, which we now watch
+  Adopted(u32),
+  // Look for such lock screens
+  Rescan,
+  // The locker with that PID exited
+  // PID 0 is a locker that could not be started
+  Unlocked(u32),
+  LockerFailed(u32, String),
   ReloadConfig,
   Exit,
-  SuspendOnLid(bool),
+  // None goes back to the lidaction policy from the configuration
+  SetLidAction(Option<LidAction>),
   Suspending,
   Suspended,
+  // true when the shutdown starts, false when it got cancelled
+  PrepareForShutdown(bool),
+  // The pre_shutdown hooks for the shutdown with this number are done
+  PreShutdownDone(u32),
   QueryFlag(CoreFlag, Sender<bool>),
+  QueryHistory(u64, Sender<Vec<history::Entry>>),
+  QueryStatus(Sender<Status>),
   AutoLock,
-  SetAutoLock(bool)
+  // The user did something, cancels a pending autolock
+  Activity,
+  SetAutoLock(bool),
+  // Value and after how many seconds to go back to the previous value
+  SetAutoLockFor(bool, u64),
+  SetLidActionFor(Option<LidAction>, u64),
+  QueryLidAction(Sender<LidAction>),
+  LidClosed(bool),
+  Docked(bool),
+  OnBattery(bool),
+  // Keys the input devices we read can report
+  KeysAvailable(Vec<Key>),
+  KeyPressed(Key),
+  // Lock, then go to sleep once the lock screen is up. Replies with logind's answer
+  Sleep(SleepKind, Sender<Result<(), String>>),
+  TimerExpired(Timer),
+  // Core fills in the cookie and replies with it
+  Inhibit(Inhibition, Sender<u32>),
+  // owner, cookie, reply whether something was released
+  Release(String, u32, Sender<bool>),
+  ListInhibitions(Sender<Vec<Inhibition>>),
+  NameLost(String)
 }
 
 impl fmt::Debug for CoreMessage {
@@ -35,23 +135,89 @@ impl fmt::Debug for CoreMessage {
         match *self {
             CoreMessage::Lock => write!(f, "Lock"),
             CoreMessage::Unlock => write!(f, "Unlock"),
-            CoreMessage::Locked => write!(f, "Locked"),
-            CoreMessage::Unlocked => write!(f, "Unlocked"),
             CoreMessage::Exit => write!(f, "Exit"),
             CoreMessage::AutoLock => write!(f, "AutoLock"),
+            CoreMessage::Activity => write!(f, "Activity"),
             CoreMessage::Suspending => write!(f, "Suspending"),
             CoreMessage::Suspended => write!(f, "Suspended"),
+            CoreMessage::PrepareForShutdown(active) => {
+                write!(f, "PrepareForShutdown({:?})", active)
+            },
+            CoreMessage::PreShutdownDone(shutdown) => {
+                write!(f, "PreShutdownDone({:?})", shutdown)
+            },
             CoreMessage::ReloadConfig => write!(f, "ReloadConfig"),
+            CoreMessage::Rescan => write!(f, "Rescan"),
 
-            CoreMessage::SuspendOnLid(flag) => {
-                write!(f, "SuspendOnLid({:?})", flag)
+            CoreMessage::Started(pid) => {
+                write!(f, "Started({:?})", pid)
+            },
+            CoreMessage::Locked(pid) => {
+                write!(f, "Locked({:?})", pid)
+            },
+            CoreMessage::SessionLock(lock) => {
+                write!(f, "SessionLock({:?})", lock)
+            },
+            CoreMessage::Adopted(pid) => {
+                write!(f, "Adopted({:?})", pid)
+            },
+            CoreMessage::Unlocked(pid) => {
+                write!(f, "Unlocked({:?})", pid)
+            },
+            CoreMessage::LockerFailed(pid, ref error) => {
+                write!(f, "LockerFailed({:?}, {:?})", pid, error)
+            },
+            CoreMessage::SetLidAction(action) => {
+                write!(f, "SetLidAction({:?})", action)
             },
             CoreMessage::QueryFlag(ref flag, _) => {
                 write!(f, "QueryFlag({:?})", flag)
             },
+            CoreMessage::QueryHistory(since, _) => {
+                write!(f, "QueryHistory({:?})", since)
+            },
+            CoreMessage::QueryStatus(_) => write!(f, "QueryStatus"),
             CoreMessage::SetAutoLock(flag) => {
                 write!(f, "SetAutoLock({:?})", flag)
             },
+            CoreMessage::SetAutoLockFor(flag, secs) => {
+                write!(f, "SetAutoLockFor({:?}, {:?})", flag, secs)
+            },
+            CoreMessage::SetLidActionFor(action, secs) => {
+                write!(f, "SetLidActionFor({:?}, {:?})", action, secs)
+            },
+            CoreMessage::QueryLidAction(_) => write!(f, "QueryLidAction"),
+            CoreMessage::LidClosed(closed) => {
+                write!(f, "LidClosed({:?})", closed)
+            },
+            CoreMessage::Docked(docked) => {
+                write!(f, "Docked({:?})", docked)
+            },
+            CoreMessage::OnBattery(on_battery) => {
+                write!(f, "OnBattery({:?})", on_battery)
+            },
+            CoreMessage::KeysAvailable(ref keys) => {
+                write!(f, "KeysAvailable({:?})", keys)
+            },
+            CoreMessage::KeyPressed(key) => {
+                write!(f, "KeyPressed({:?})", key)
+            },
+            CoreMessage::Sleep(kind, _) => {
+                write!(f, "Sleep({:?})", kind)
+            },
+            CoreMessage::TimerExpired(timer) => {
+                write!(f, "TimerExpired({:?})", timer)
+            },
+            CoreMessage::Inhibit(ref inhibition, _) => {
+                write!(f, "Inhibit({:?})", inhibition)
+            },
+            CoreMessage::Release(ref owner, cookie, _) => {
+                write!(f, "Release({:?}, {:?})", owner, cookie)
+            },
+            CoreMessage::ListInhibitions(_) => write!(f, "ListInhibitions"),
+            CoreMessage::NameLost(ref name) => {
+                write!(f, "NameLost({:?})", name)
+            },
         }
     }
 }
@@ -63,3 +229,285 @@ pub enum CoreFlag {
   //Locked,
   AutoLock
 }
+
+/// Snapshot of the daemon state for GetStatus
+pub struct Status {
+  pub locked: bool,
+  pub locking: bool,
+  pub locked_since: u64,
+  pub lock_reason: Option<LockReason>,
+  pub autolock: bool,
+  // Seconds until autolock goes back to its previous value, 0 if it stays
+  pub autolock_revert_in: u64,
+  pub lid_action: LidAction,
+  // Whether the lid action comes from the policy rather than lockctl
+  pub lid_action_auto: bool,
+  pub docked: bool,
+  pub on_battery: bool,
+  pub lidaction_revert_in: u64,
+  pub inhibitors: Vec<String>,
+  pub inhibitions: Vec<String>,
+  pub locker_pid: Option<u32>,
+  pub config_path: String
+}
+
+/// What caused the screen to be locked
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LockReason {
+  Manual,
+  AutoLock,
+  Suspend,
+  Lid,
+  Key,
+  // loginctl lock-session and the like
+  Session,
+  // Already running when we found it
+  Adopted
+}
+
+impl LockReason {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LockReason::Manual => "manual",
+            LockReason::AutoLock => "autolock",
+            LockReason::Suspend => "suspend",
+            LockReason::Lid => "lid",
+            LockReason::Key => "key",
+            LockReason::Session => "session",
+            LockReason::Adopted => "adopted"
+        }
+    }
+
+    /// The reasons the triggers block of the configuration can pick a locker for
+    pub fn from_trigger(name: &str) -> Option<LockReason> {
+        match name {
+            "manual" => Some(LockReason::Manual),
+            "autolock" => Some(LockReason::AutoLock),
+            "suspend" => Some(LockReason::Suspend),
+            "lid" => Some(LockReason::Lid),
+            "key" => Some(LockReason::Key),
+            "session" => Some(LockReason::Session),
+            _ => None
+        }
+    }
+}
+
+/// What happens between autolock being triggered and the screen locking, from the autolock_warning block
+#[derive(Debug, Clone, Default)]
+pub struct AutoLockWarning {
+  // Zero locks right away
+  pub duration: Duration,
+  pub notify: bool,
+  // Percentage of the current backlight brightness to dim to
+  pub dim: Option<u32>,
+  // Run when the warning starts, and when it gets cancelled
+  pub command: Option<(String, Vec<String>)>,
+  pub cancel_command: Option<(String, Vec<String>)>
+}
+
+/// Where to find the backlight, from the backlight block of the configuration
+#[derive(Debug, Clone)]
+pub struct Backlight {
+  // Directory with one directory per device, like /sys/class/backlight
+  pub sysfs: String,
+  // None takes the first one
+  pub device: Option<String>,
+  // Bus name of logind, which sets the brightness for us, and the bus to find it on
+  pub logind: String,
+  pub bus: BusType
+}
+
+/// How to turn the display off and on again, from blank_off and blank_on of the configuration
+#[derive(Debug, Clone)]
+pub enum BlankAction {
+  // Off and optionally on
+  Command((String, Vec<String>), Option<(String, Vec<String>)>),
+  // Backlight to zero through logind and back
+  Backlight
+}
+
+/// A lock command with its arguments
+#[derive(Debug, Clone)]
+pub struct LockCommand {
+  pub program: String,
+  pub args: Vec<Template>,
+  // How long after starting it the screen counts as locked, as nothing tells us when the locker grabbed it
+  pub ready_delay: Duration,
+  // None uses unlock_signal
+  pub unlock_signal: Option<i32>
+}
+
+/// Which lock command runs for which lock reason, from lock_cmd and the lockers and triggers blocks
+#[derive(Debug, Clone)]
+pub struct LockerTable {
+  // lock_cmd, for reasons without a trigger
+  pub default: LockCommand,
+  pub triggers: Vec<(LockReason, LockCommand)>
+}
+
+impl LockerTable {
+    pub fn command(&self, reason: LockReason) -> &LockCommand {
+        self.triggers.iter().find(|&&(r, _)| r == reason).map_or(&self.default, |&(_, ref c)| c)
+    }
+
+    /// Every program that may be a lock screen of ours
+    pub fn programs(&self) -> Vec<&str> {
+        let mut ret = vec![&*self.default.program];
+        for &(_, ref command) in &self.triggers {
+            if !ret.contains(&&*command.program) {
+                ret.push(&command.program);
+            }
+        }
+        ret
+    }
+}
+
+/// What happens when the lid gets closed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LidAction {
+  // logind does it, we just stay out of the way
+  Suspend,
+  Hibernate,
+  HybridSleep,
+  Lock,
+  LockAndBlank,
+  Ignore
+}
+
+impl LidAction {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            LidAction::Suspend => "suspend",
+            LidAction::Hibernate => "hibernate",
+            LidAction::HybridSleep => "hybrid-sleep",
+            LidAction::Lock => "lock",
+            LidAction::LockAndBlank => "lock-and-blank",
+            LidAction::Ignore => "ignore"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<LidAction> {
+        match name {
+            "suspend" => Some(LidAction::Suspend),
+            "hibernate" => Some(LidAction::Hibernate),
+            "hybrid-sleep" => Some(LidAction::HybridSleep),
+            "lock" => Some(LidAction::Lock),
+            "lock-and-blank" => Some(LidAction::LockAndBlank),
+            "ignore" => Some(LidAction::Ignore),
+            _ => None
+        }
+    }
+}
+
+/// Lid action depending on the power state, from the lidaction block of the configuration
+#[derive(Debug, Clone, Copy)]
+pub struct LidPolicy {
+  pub battery: LidAction,
+  pub ac: LidAction,
+  // Wins over battery and ac
+  pub docked: LidAction
+}
+
+impl LidPolicy {
+    pub fn action(&self, docked: bool, on_battery: bool) -> LidAction {
+        if docked {
+            self.docked
+        } else if on_battery {
+            self.battery
+        } else {
+            self.ac
+        }
+    }
+}
+
+/// Environment of the lock command, from the environment block of the configuration.
+/// Put together every time the lock command starts, in the order of the fields.
+#[derive(Debug, Clone, Default)]
+pub struct LockEnvironment {
+  // Start from nothing instead of our own environment
+  pub clear: bool,
+  // Variables to take from the systemd user manager, all of them if empty
+  pub import_systemd: Option<Vec<String>>,
+  // NAME=value lines
+  pub env_file: Option<String>,
+  pub vars: Vec<(String, String)>,
+  pub working_dir: Option<String>
+}
+
+/// Keys logind handles unless we take them over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+  Power,
+  Suspend,
+  Hibernate
+}
+
+impl Key {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Key::Power => "power",
+            Key::Suspend => "suspend",
+            Key::Hibernate => "hibernate"
+        }
+    }
+
+    /// What logind calls the inhibitor that keeps it from handling the key
+    pub fn inhibitor(&self) -> &'static str {
+        match *self {
+            Key::Power => "handle-power-key",
+            Key::Suspend => "handle-suspend-key",
+            Key::Hibernate => "handle-hibernate-key"
+        }
+    }
+}
+
+/// What to do when a key we took over is pressed
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyAction {
+  Lock,
+  Suspend,
+  Hibernate,
+  LockThenSuspend,
+  // Program and arguments
+  Command(String, Vec<String>),
+  Ignore
+}
+
+/// Things a client can temporarily switch off through the Inhibit call
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InhibitKind {
+  AutoLock,
+  Lid,
+  // Do not lock the screen before going to sleep
+  SleepLock
+}
+
+impl InhibitKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            InhibitKind::AutoLock => "autolock",
+            InhibitKind::Lid => "lid",
+            InhibitKind::SleepLock => "sleep-lock"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<InhibitKind> {
+        match name {
+            "autolock" => Some(InhibitKind::AutoLock),
+            "lid" => Some(InhibitKind::Lid),
+            "sleep-lock" => Some(InhibitKind::SleepLock),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Inhibition {
+  pub cookie: u32,
+  pub kind: InhibitKind,
+  // Human readable name of the client
+  pub who: String,
+  pub why: String,
+  // Unique bus name of the client, the inhibition goes away with it
+  pub owner: String
+}
@@ -0,0 +1,88 @@
+// Desktop notifications through org.freedesktop.Notifications, for now only the autolock warning.
+
+use dbus::{Connection, BusType, ConnectionItem, Message, MessageItem};
+use std::borrow::Cow;
+use std::sync::mpsc::{Sender, Receiver, TryRecvError};
+
+use msg::{NotifyMessage, CoreMessage};
+
+pub fn actor_notify(core: Sender<CoreMessage>, inbox: Receiver<NotifyMessage>) {
+    let con = match Connection::get_private(BusType::Session) {
+        Ok(c) => c,
+        Err(e) => {
+            println!("Warning: no session bus, cannot show notifications: {:?}", e);
+            for _ in inbox {}
+            return;
+        }
+    };
+    con.add_match("type='signal',path='/org/freedesktop/Notifications',interface='org.freedesktop.Notifications'").unwrap();
+    // The notification on screen, if any
+    let mut current: Option<u32> = None;
+    // Nothing to wait for on the bus until we show something, afterwards check the inbox four times a second
+    let mut message = inbox.recv().ok();
+    let mut items = con.iter(250);
+    loop {
+        match message {
+            Some(NotifyMessage::Show(summary, body, timeout)) => {
+                current = show(&con, current, &summary, &body, timeout);
+            },
+            Some(NotifyMessage::Close) => {
+                if let Some(id) = current.take() {
+                    let m = Message::new_method_call("org.freedesktop.Notifications", "/org/freedesktop/Notifications", "org.freedesktop.Notifications", "CloseNotification").unwrap()
+                        .append1(id);
+                    let _ = con.send(m);
+                }
+            },
+            None => {}
+        }
+
+        match items.next() {
+            Some(ConnectionItem::Signal(msg)) => {
+                let member = msg.member().map_or(String::new(), |m| m.to_string());
+                if member == "ActionInvoked" {
+                    match msg.get2::<u32, &str>() {
+                        (Some(id), Some("cancel")) if Some(id) == current => {
+                            current = None;
+                            core.send(CoreMessage::Activity).unwrap();
+                        },
+                        _ => {}
+                    }
+                } else if member == "NotificationClosed" {
+                    if current.is_some() && msg.get1::<u32>() == current {
+                        current = None;
+                    }
+                }
+            },
+            _ => {}
+        }
+
+        message = match inbox.try_recv() {
+            Ok(m) => Some(m),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => return
+        };
+    }
+}
+
+// Shows a notification with a Cancel action, replacing the one on screen. Returns its ID.
+fn show(con: &Connection, replaces: Option<u32>, summary: &str, body: &str, timeout: u32) -> Option<u32> {
+    let mut m = Message::new_method_call("org.freedesktop.Notifications", "/org/freedesktop/Notifications", "org.freedesktop.Notifications", "Notify").unwrap();
+    m.append_items(&[
+        MessageItem::Str(format!("lockd")),
+        MessageItem::UInt32(replaces.unwrap_or(0)),
+        MessageItem::Str(format!("system-lock-screen")),
+        MessageItem::Str(summary.to_string()),
+        MessageItem::Str(body.to_string()),
+        // Action key and label
+        MessageItem::Array(vec![MessageItem::Str(format!("cancel")), MessageItem::Str(format!("Cancel"))], Cow::Borrowed("s")),
+        MessageItem::Array(Vec::new(), Cow::Borrowed("{sv}")),
+        MessageItem::Int32(timeout as i32)
+    ]);
+    match con.send_with_reply_and_block(m, 2000) {
+        Ok(r) => r.get1(),
+        Err(e) => {
+            println!("Warning: could not show notification: {}", e.message().unwrap_or(""));
+            None
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use dbus::{Connection, BusType, Message, MessageItem};
+use libc;
+use std::mem;
+
+/// The sleep states logind can put the machine into
+#[derive(Debug, Clone, Copy)]
+pub enum SleepKind {
+  Suspend,
+  Hibernate,
+  HybridSleep
+}
+
+impl SleepKind {
+  pub fn name(&self) -> &'static str {
+    match *self {
+      SleepKind::Suspend => "suspend",
+      SleepKind::Hibernate => "hibernate",
+      SleepKind::HybridSleep => "hybrid-sleep"
+    }
+  }
+
+  pub fn from_name(name: &str) -> Option<SleepKind> {
+    match name {
+      "suspend" => Some(SleepKind::Suspend),
+      "hibernate" => Some(SleepKind::Hibernate),
+      "hybrid-sleep" => Some(SleepKind::HybridSleep),
+      _ => None
+    }
+  }
+
+  fn method(&self) -> &'static str {
+    match *self {
+      SleepKind::Suspend => "Suspend",
+      SleepKind::Hibernate => "Hibernate",
+      SleepKind::HybridSleep => "HybridSleep"
+    }
+  }
+}
+
+/// Asks logind to put the machine to sleep. Returns once logind accepted the request,
+/// our own sleep delay inhibitor still gets to lock the screen first.
+pub fn sleep(kind: SleepKind) -> Result<(), String> {
+  let con = try!(Connection::get_private(BusType::System).map_err(|e| format!("{:?}", e)));
+  let m = Message::new_method_call("org.freedesktop.login1", "/org/freedesktop/login1", "org.freedesktop.login1.Manager", kind.method()).unwrap()
+    // Not interactive, polkit should not ask for a password
+    .append1(false);
+  match con.send_with_reply_and_block(m, 5000) {
+    Ok(_) => Ok(()),
+    Err(e) => {
+      let mut error = format!("{}: {}", e.name().unwrap_or("unknown error"), e.message().unwrap_or(""));
+      let blockers = blockers(&con);
+      if !blockers.is_empty() {
+        error.push_str(&format!(", inhibited by {}", blockers.join(", ")));
+      }
+      Err(error)
+    }
+  }
+}
+
+// Who holds a block inhibitor on sleep, as "who (why)"
+fn blockers(con: &Connection) -> Vec<String> {
+  let m = Message::new_method_call("org.freedesktop.login1", "/org/freedesktop/login1", "org.freedesktop.login1.Manager", "ListInhibitors").unwrap();
+  let r = match con.send_with_reply_and_block(m, 2000) {
+    Ok(r) => r,
+    Err(_) => return Vec::new()
+  };
+  let items = r.get_items();
+  let inhibitors = match items.get(0) {
+    Some(&MessageItem::Array(ref a, _)) => a,
+    _ => return Vec::new()
+  };
+  // a(ssssuu): what, who, why, mode, uid, pid
+  inhibitors.iter().filter_map(|i| match *i {
+    MessageItem::Struct(ref fields) => match (fields.get(0), fields.get(1), fields.get(2), fields.get(3)) {
+      (Some(&MessageItem::Str(ref what)), Some(&MessageItem::Str(ref who)), Some(&MessageItem::Str(ref why)), Some(&MessageItem::Str(ref mode)))
+        if mode == "block" && what.split(':').any(|w| w == "sleep") => Some(format!("{} ({})", who, why)),
+      _ => None
+    },
+    _ => None
+  }).collect()
+}
+
+fn clock(id: libc::clockid_t) -> Option<u64> {
+  unsafe {
+    let mut ts: libc::timespec = mem::zeroed();
+    if libc::clock_gettime(id, &mut ts) == 0 {
+      Some(ts.tv_sec as u64)
+    } else {
+      None
+    }
+  }
+}
+
+/// Seconds spent asleep since boot. The boot time clock keeps running during suspend, the monotonic one does not.
+pub fn slept_total() -> Option<u64> {
+  match (clock(libc::CLOCK_BOOTTIME), clock(libc::CLOCK_MONOTONIC)) {
+    (Some(boot), Some(mono)) => Some(boot.saturating_sub(mono)),
+    _ => None
+  }
+}
@@ -0,0 +1,135 @@
+// Looks at processes through /proc, to tell our locker from whatever got its PID later.
+
+use libc;
+use std::fs;
+use std::io::{self, Read};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+use std::process;
+
+struct Stat {
+    state: char,
+    ppid: u32,
+    // Clock ticks after boot the process started at
+    starttime: u64
+}
+
+fn stat(pid: u32) -> Option<Stat> {
+    let s = match read(&format!("/proc/{}/stat", pid)) {
+        Some(s) => s,
+        None => return None
+    };
+    // The command name comes second in parentheses and may contain anything, so start after it
+    let rest = match s.rfind(')') {
+        Some(i) => &s[i + 1..],
+        None => return None
+    };
+    let fields: Vec<&str> = rest.split_whitespace().collect();
+    // rest starts with field 3 (state), starttime is field 22
+    match (fields.get(0).and_then(|f| f.chars().next()), fields.get(1).and_then(|f| f.parse().ok()), fields.get(19).and_then(|f| f.parse().ok())) {
+        (Some(state), Some(ppid), Some(starttime)) => Some(Stat { state: state, ppid: ppid, starttime: starttime }),
+        _ => None
+    }
+}
+
+/// Start time of a process, to recognize it later
+pub fn starttime(pid: u32) -> Option<u64> {
+    stat(pid).map(|s| s.starttime)
+}
+
+/// Whether pid is still the running process that started at starttime
+pub fn is_alive(pid: u32, starttime: u64) -> bool {
+    match stat(pid) {
+        Some(s) => s.state != 'Z' && s.state != 'X' && s.starttime == starttime,
+        None => false
+    }
+}
+
+/// Whether anything is left in the process group
+pub fn group_alive(pgid: u32) -> bool {
+    if unsafe { libc::kill(-(pgid as libc::pid_t), 0) } == 0 {
+        return true;
+    }
+    // EPERM is a setuid process in the group
+    io::Error::last_os_error().raw_os_error() != Some(libc::ESRCH)
+}
+
+fn read(path: &str) -> Option<String> {
+    let mut s = String::new();
+    match fs::File::open(path).and_then(|mut f| f.read_to_string(&mut s)) {
+        Ok(_) => Some(s),
+        Err(_) => None
+    }
+}
+
+// Audit session ID, the one logind sessions are keyed by
+fn session(pid: &str) -> Option<String> {
+    match read(&format!("/proc/{}/sessionid", pid)) {
+        // Not set without a login session
+        Some(ref s) if s.trim() == "4294967295" => None,
+        Some(s) => Some(s.trim().to_string()),
+        None => None
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path)
+}
+
+fn is_interpreter(program: &str) -> bool {
+    match program {
+        "sh" | "bash" | "dash" | "zsh" | "ksh" | "fish" | "perl" | "ruby" => true,
+        // python3, python3.12 and so on
+        _ => program.starts_with("python")
+    }
+}
+
+/// Finds a running lock screen we did not start: a process of our user in our session
+/// running one of commands, directly or through an interpreter. Returns the newest one with its start time.
+pub fn find_locker(commands: &[&str]) -> Option<(u32, u64)> {
+    let entries = match fs::read_dir("/proc") {
+        Ok(e) => e,
+        Err(_) => return None
+    };
+    let uid = unsafe { libc::getuid() };
+    let own_session = session("self");
+    let names: Vec<&str> = commands.iter().map(|c| file_name(c)).collect();
+    let mut found: Option<(u32, u64)> = None;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let pid: u32 = match entry.file_name().to_str().and_then(|p| p.parse().ok()) {
+            Some(p) => p,
+            None => continue
+        };
+        // Our own children are tracked already
+        if pid == process::id() || entry.metadata().map(|m| m.uid() != uid).unwrap_or(true) {
+            continue;
+        }
+        let pid_str = pid.to_string();
+        if own_session.is_some() && session(&pid_str) != own_session {
+            continue;
+        }
+        let stat = match stat(pid) {
+            Some(s) => s,
+            None => continue
+        };
+        if stat.ppid == process::id() || stat.state == 'Z' {
+            continue;
+        }
+        let cmdline = match read(&format!("/proc/{}/cmdline", pid)) {
+            Some(c) => c,
+            None => continue
+        };
+        let mut args = cmdline.split('\0');
+        let program = file_name(args.next().unwrap_or(""));
+        // The script is the second argument when it runs through an interpreter. For anything else that
+        // is just an argument, like in man i3lock.
+        let script = if is_interpreter(program) { args.next().map_or("", file_name) } else { "" };
+        if !names.contains(&program) && (script.is_empty() || !names.contains(&script)) {
+            continue;
+        }
+        if found.map_or(true, |(_, t)| stat.starttime > t) {
+            found = Some((pid, stat.starttime));
+        }
+    }
+    found
+}
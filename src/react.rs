@@ -1,21 +1,61 @@
-use dbus::{Connection, BusType, ConnectionItem};
+use dbus::{Connection, BusType, ConnectionItem, MessageItem, Props};
 use std::sync::mpsc::{Sender};
 use msg::CoreMessage;
+use logind;
+
+// Last seen values of the properties we poll, None if we could not get them
+struct Watched {
+  lid_closed: Option<bool>,
+  docked: Option<bool>,
+  on_battery: Option<bool>,
+  // Of our session, if we found it
+  session: Option<String>,
+  idle: Option<bool>
+}
 
 pub fn actor_react(core: Sender<CoreMessage>) {
   let con = Connection::get_private(BusType::System).unwrap();
   con.add_match("type='signal',interface='org.freedesktop.login1.Manager'").unwrap();
-  for event in con.iter(60_000) {
+  con.add_match("type='signal',path='/org/freedesktop/login1',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'").unwrap();
+  con.add_match("type='signal',path='/org/freedesktop/UPower',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'").unwrap();
+  // loginctl lock-session and unlock-session, and IdleHint
+  let session = logind::session_path(&con, "org.freedesktop.login1");
+  match session {
+    Some(ref path) => {
+      con.add_match(&format!("type='signal',path='{}',interface='org.freedesktop.login1.Session'", path)).unwrap();
+      con.add_match(&format!("type='signal',path='{}',interface='org.freedesktop.DBus.Properties',member='PropertiesChanged'", path)).unwrap();
+    },
+    None => println!("Warning: could not find our logind session, ignoring its Lock and Unlock signals and IdleHint")
+  }
+  let mut watched = Watched {
+    // The lid being closed when we start is no reason to act
+    lid_closed: get_bool(&con, "org.freedesktop.login1", "/org/freedesktop/login1", "org.freedesktop.login1.Manager", "LidClosed"),
+    docked: None,
+    on_battery: None,
+    session: session,
+    idle: None
+  };
+  watched.poll(&con, &core);
+  // logind does not announce every LidClosed change, so we also poll every two seconds
+  for event in con.iter(2_000) {
     match event {
       ConnectionItem::Signal(msg) => {
         let member = msg.member().unwrap();
-        if &*member == "PrepareForSleep" {
+        let session = msg.interface().map_or(false, |i| &*i == "org.freedesktop.login1.Session");
+        if session && &*member == "Lock" {
+          core.send(CoreMessage::SessionLock(true)).unwrap();
+        } else if session && &*member == "Unlock" {
+          core.send(CoreMessage::SessionLock(false)).unwrap();
+        } else if &*member == "PrepareForSleep" {
           let active: bool = msg.get1().unwrap();
           if active {
             core.send(CoreMessage::Suspending).unwrap();
           } else {
             core.send(CoreMessage::Suspended).unwrap();
           }
+        } else if &*member == "PrepareForShutdown" {
+          let active: bool = msg.get1().unwrap();
+          core.send(CoreMessage::PrepareForShutdown(active)).unwrap();
         } else {
         }
       },
@@ -24,5 +64,50 @@ pub fn actor_react(core: Sender<CoreMessage>) {
       ConnectionItem::Nothing => {},
       ConnectionItem::WatchFd(..) => {}
     }
+    watched.poll(&con, &core);
+  }
+}
+
+impl Watched {
+  fn poll(&mut self, con: &Connection, core: &Sender<CoreMessage>) {
+    let lid_closed = get_bool(con, "org.freedesktop.login1", "/org/freedesktop/login1", "org.freedesktop.login1.Manager", "LidClosed");
+    if let Some(closed) = changed(&mut self.lid_closed, lid_closed) {
+      core.send(CoreMessage::LidClosed(closed)).unwrap();
+    }
+    let docked = get_bool(con, "org.freedesktop.login1", "/org/freedesktop/login1", "org.freedesktop.login1.Manager", "Docked");
+    if let Some(docked) = changed(&mut self.docked, docked) {
+      core.send(CoreMessage::Docked(docked)).unwrap();
+    }
+    // Without UPower we never hear about it and stay on AC
+    let on_battery = get_bool(con, "org.freedesktop.UPower", "/org/freedesktop/UPower", "org.freedesktop.UPower", "OnBattery");
+    if let Some(on_battery) = changed(&mut self.on_battery, on_battery) {
+      core.send(CoreMessage::OnBattery(on_battery)).unwrap();
+    }
+    if let Some(ref path) = self.session {
+      let idle = get_bool(con, "org.freedesktop.login1", path, "org.freedesktop.login1.Session", "IdleHint");
+      // The user came back, which cancels a pending autolock. Not when we first learn the session is not idle.
+      let was_idle = self.idle == Some(true);
+      if changed(&mut self.idle, idle) == Some(false) && was_idle {
+        core.send(CoreMessage::Activity).unwrap();
+      }
+    }
+  }
+}
+
+// Remembers the new value and returns it if it is known and differs from the last one.
+// A failed read keeps the last known value.
+fn changed(last: &mut Option<bool>, new: Option<bool>) -> Option<bool> {
+  if new.is_none() || new == *last {
+    return None;
+  }
+  *last = new;
+  new
+}
+
+fn get_bool(con: &Connection, service: &str, path: &str, interface: &str, name: &str) -> Option<bool> {
+  let props = Props::new(con, service, path, interface, 2000);
+  match props.get(name) {
+    Ok(MessageItem::Bool(value)) => Some(value),
+    _ => None
   }
 }
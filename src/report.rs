@@ -0,0 +1,234 @@
+// Turns the lock history into per day active/away times for lockctl report.
+//
+// Locked and suspended time both count as away, overlapping periods (locked, then
+// suspended while locked) are merged. Only the time between the first and the last
+// activity of a day is reported, so the screen being locked overnight does not show up.
+
+use json;
+use localtime;
+
+pub struct Day {
+    pub start: u64,
+    pub first: u64,
+    pub last: u64,
+    pub active: u64,
+    pub away: u64,
+    pub breaks: Vec<(u64, u64)>
+}
+
+pub enum Format {
+    Table,
+    Csv,
+    Json
+}
+
+// (start, end, away)
+type Segment = (u64, u64, bool);
+
+fn segments(entries: &[(u64, String, String, u64)], now: u64) -> Vec<Segment> {
+    let mut ret: Vec<Segment> = Vec::new();
+    let mut locked = false;
+    let mut suspended = false;
+    let mut since = match entries.first() {
+        Some(e) => e.0,
+        None => return ret
+    };
+
+    for &(time, ref event, _, duration) in entries {
+        let was_away = locked || suspended;
+        match &**event {
+            // A lock that failed leaves locking and locker_crash without locked or unlocked
+            "locked" => locked = true,
+            "unlocked" | "locker_crash" => locked = false,
+            "suspend" => suspended = true,
+            "resume" => {
+                if !suspended && duration > 0 {
+                    // lockd missed the suspend, the resume still knows how long we slept
+                    let start = time.saturating_sub(duration).max(since);
+                    push(&mut ret, since, start, was_away);
+                    since = start;
+                    push(&mut ret, since, time, true);
+                    since = time;
+                }
+                suspended = false;
+            },
+            _ => {}
+        }
+        if was_away != (locked || suspended) {
+            push(&mut ret, since, time, was_away);
+            since = time;
+        }
+    }
+    push(&mut ret, since, now.max(since), locked || suspended);
+    ret
+}
+
+fn push(segments: &mut Vec<Segment>, start: u64, end: u64, away: bool) {
+    if end <= start {
+        return;
+    }
+    if let Some(last) = segments.last_mut() {
+        if last.2 == away && last.1 == start {
+            last.1 = end;
+            return;
+        }
+    }
+    segments.push((start, end, away));
+}
+
+/// Splits segments at local midnight
+fn split_days(segments: Vec<Segment>) -> Vec<(u64, Vec<Segment>)> {
+    let mut ret: Vec<(u64, Vec<Segment>)> = Vec::new();
+    for (mut start, end, away) in segments {
+        while start < end {
+            let day = localtime::start_of_day(start);
+            // Adding 25 hours and going back to midnight copes with DST changes
+            let next = localtime::start_of_day(day + 25 * 60 * 60);
+            let piece_end = end.min(next);
+            let new_day = match ret.last() {
+                Some(&(d, _)) => d != day,
+                None => true
+            };
+            if new_day {
+                ret.push((day, Vec::new()));
+            }
+            ret.last_mut().unwrap().1.push((start, piece_end, away));
+            start = piece_end;
+        }
+    }
+    ret
+}
+
+pub fn days(entries: &[(u64, String, String, u64)], now: u64, threshold: u64) -> Vec<Day> {
+    let mut ret = Vec::new();
+    for (day, segments) in split_days(segments(entries, now)) {
+        let active: Vec<&Segment> = segments.iter().filter(|s| !s.2).collect();
+        let (first, last) = match (active.first(), active.last()) {
+            (Some(f), Some(l)) => (f.0, l.1),
+            // Away the whole day
+            _ => continue
+        };
+        let mut d = Day {
+            start: day,
+            first: first,
+            last: last,
+            active: 0,
+            away: 0,
+            breaks: Vec::new()
+        };
+        for &(start, end, away) in segments.iter().filter(|s| s.0 >= first && s.1 <= last) {
+            if away {
+                d.away += end - start;
+                if end - start >= threshold {
+                    d.breaks.push((start, end));
+                }
+            } else {
+                d.active += end - start;
+            }
+        }
+        ret.push(d);
+    }
+    ret
+}
+
+/// Like 2h 5m, 3m 20s or 45s, also used by lockctl status and history
+pub fn format_duration(secs: u64) -> String {
+    if secs >= 60 * 60 {
+        format!("{}h {}m", secs / (60 * 60), secs / 60 % 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+pub fn print(days: &[Day], format: Format) {
+    match format {
+        Format::Table => {
+            println!("{:<10}  {:<5}  {:<5}  {:>8}  {:>8}  {}", "Date", "First", "Last", "Active", "Away", "Breaks");
+            for d in days {
+                let breaks: Vec<String> = d.breaks.iter().map(|&(s, e)| {
+                    format!("{}-{} ({})", localtime::format(s, "%H:%M"), localtime::format(e, "%H:%M"), format_duration(e - s))
+                }).collect();
+                println!("{:<10}  {:<5}  {:<5}  {:>8}  {:>8}  {}",
+                         localtime::format(d.start, "%Y-%m-%d"),
+                         localtime::format(d.first, "%H:%M"),
+                         localtime::format(d.last, "%H:%M"),
+                         format_duration(d.active),
+                         format_duration(d.away),
+                         breaks.join(", "));
+            }
+        },
+        Format::Csv => {
+            println!("date,first,last,active_seconds,away_seconds,breaks");
+            for d in days {
+                let breaks: Vec<String> = d.breaks.iter().map(|&(s, e)| {
+                    format!("{}-{}", localtime::format(s, "%H:%M"), localtime::format(e, "%H:%M"))
+                }).collect();
+                println!("{},{},{},{},{},{}",
+                         localtime::format(d.start, "%Y-%m-%d"),
+                         localtime::format(d.first, "%H:%M:%S"),
+                         localtime::format(d.last, "%H:%M:%S"),
+                         d.active, d.away, breaks.join(" "));
+            }
+        },
+        Format::Json => {
+            let days: Vec<String> = days.iter().map(|d| {
+                let breaks: Vec<String> = d.breaks.iter().map(|&(s, e)| {
+                    format!("{{\"start\":{},\"end\":{}}}", s, e)
+                }).collect();
+                format!("{{\"date\":{},\"first\":{},\"last\":{},\"active\":{},\"away\":{},\"breaks\":[{}]}}",
+                        json::escape(&localtime::format(d.start, "%Y-%m-%d")),
+                        d.first, d.last, d.active, d.away, breaks.join(","))
+            }).collect();
+            println!("[{}]", days.join(","));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use localtime;
+    use super::{days, segments};
+
+    fn entries(list: &[(u64, &str, u64)]) -> Vec<(u64, String, String, u64)> {
+        list.iter().map(|&(time, event, duration)| (time, event.to_string(), String::new(), duration)).collect()
+    }
+
+    #[test]
+    fn suspend_while_locked() {
+        let e = entries(&[(1000, "config_reload", 0), (1100, "locking", 0), (1105, "locked", 0), (1200, "suspend", 0),
+                          (1500, "resume", 300), (1600, "unlocked", 495)]);
+        assert_eq!(segments(&e, 2000), vec![(1000, 1105, false), (1105, 1600, true), (1600, 2000, false)]);
+    }
+
+    #[test]
+    fn resume_without_suspend() {
+        let e = entries(&[(1000, "config_reload", 0), (1500, "resume", 300)]);
+        assert_eq!(segments(&e, 2000), vec![(1000, 1200, false), (1200, 1500, true), (1500, 2000, false)]);
+    }
+
+    #[test]
+    fn failed_lock() {
+        let e = entries(&[(1000, "config_reload", 0), (1100, "locking", 0), (1101, "locker_crash", 0)]);
+        assert_eq!(segments(&e, 2000), vec![(1000, 2000, false)]);
+    }
+
+    #[test]
+    fn crash_while_locked() {
+        let e = entries(&[(1000, "locking", 0), (1001, "locked", 0), (1300, "locker_crash", 299), (1300, "unlocked", 299)]);
+        assert_eq!(segments(&e, 2000), vec![(1000, 1001, false), (1001, 1300, true), (1300, 2000, false)]);
+    }
+
+    #[test]
+    fn split_at_midnight() {
+        // Whatever the local time zone, midnight is known through localtime
+        let midnight = localtime::start_of_day(1500000000 + 2 * 24 * 60 * 60);
+        let e = entries(&[(midnight - 3600, "config_reload", 0), (midnight - 1800, "locked", 0),
+                          (midnight + 1800, "unlocked", 3600)]);
+        let d = days(&e, midnight + 3600, 600);
+        assert_eq!(d.len(), 2);
+        assert_eq!((d[0].first, d[0].last, d[0].active, d[0].away), (midnight - 3600, midnight - 1800, 1800, 0));
+        assert_eq!((d[1].start, d[1].first, d[1].last, d[1].active, d[1].away), (midnight, midnight + 1800, midnight + 3600, 1800, 0));
+    }
+}
@@ -0,0 +1,238 @@
+// Placeholders in lock_cmd arguments, filled in every time the screen locks.
+//
+// {reason}, {user}, {hostname}, {time:FORMAT} (strftime, {time} is %H:%M), {random_file:PATTERN}
+// (* and ? in the file name only, ~ is the home directory) and {env:VAR}. {{ and }} stand for { and }.
+
+use libc;
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CStr, OsStr, OsString};
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use history;
+use localtime;
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Reason,
+    User,
+    Hostname,
+    Time(String),
+    RandomFile(String, String),
+    Env(String)
+}
+
+/// A parsed lock_cmd argument
+#[derive(Debug, Clone)]
+pub struct Template {
+    parts: Vec<Part>
+}
+
+impl Template {
+    /// An argument without placeholders
+    pub fn text(s: &str) -> Template {
+        Template { parts: vec![Part::Text(s.to_string())] }
+    }
+
+    /// Parses an argument, so mistakes show up when the configuration is loaded and not on lock
+    pub fn parse(s: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                },
+                '{' => {
+                    let mut placeholder = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') => return Err(format!("{{ inside a placeholder in \"{}\", write {{{{ for a literal {{", s)),
+                            Some(c) => placeholder.push(c),
+                            None => return Err(format!("unclosed {{ in \"{}\", write {{{{ for a literal {{", s))
+                        }
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(text));
+                        text = String::new();
+                    }
+                    parts.push(try!(placeholder_part(&placeholder)));
+                },
+                '}' => return Err(format!("unmatched }} in \"{}\", write }}}} for a literal }}", s)),
+                c => text.push(c)
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Template { parts: parts })
+    }
+
+    /// Fills in the placeholders, {env:VAR} from vars. What cannot be found becomes empty.
+    pub fn render(&self, reason: &str, vars: &HashMap<OsString, OsString>) -> String {
+        self.parts.iter().map(|part| match *part {
+            Part::Text(ref text) => text.clone(),
+            Part::Reason => reason.to_string(),
+            Part::User => user(),
+            Part::Hostname => hostname(),
+            Part::Time(ref format) => localtime::format(history::now(), format),
+            Part::RandomFile(ref dir, ref pattern) => random_file(dir, pattern),
+            Part::Env(ref name) => vars.get(OsStr::new(name)).map_or(String::new(), |v| v.to_string_lossy().into_owned())
+        }).collect()
+    }
+}
+
+fn placeholder_part(placeholder: &str) -> Result<Part, String> {
+    let (name, arg) = match placeholder.find(':') {
+        Some(i) => (&placeholder[..i], Some(&placeholder[i + 1..])),
+        None => (placeholder, None)
+    };
+    match (name, arg) {
+        ("reason", None) => Ok(Part::Reason),
+        ("user", None) => Ok(Part::User),
+        ("hostname", None) => Ok(Part::Hostname),
+        ("time", None) => Ok(Part::Time(format!("%H:%M"))),
+        ("time", Some(format)) => Ok(Part::Time(format.to_string())),
+        ("random_file", Some(pattern)) if !pattern.is_empty() => {
+            let (dir, file) = match pattern.rfind('/') {
+                Some(i) => (&pattern[..i + 1], &pattern[i + 1..]),
+                None => ("./", pattern)
+            };
+            if dir.contains('*') || dir.contains('?') {
+                return Err(format!("{{random_file:{}}} may only have wildcards in the file name", pattern));
+            }
+            Ok(Part::RandomFile(dir.to_string(), file.to_string()))
+        },
+        ("env", Some(var)) if !var.is_empty() => Ok(Part::Env(var.to_string())),
+        ("reason", _) | ("user", _) | ("hostname", _) => Err(format!("{{{}}} does not take an argument", name)),
+        ("random_file", _) => Err(format!("{{random_file:PATTERN}} needs a pattern")),
+        ("env", _) => Err(format!("{{env:VAR}} needs a variable name")),
+        _ => Err(format!("unknown placeholder {{{}}}, expected reason, user, hostname, time, random_file or env", placeholder))
+    }
+}
+
+fn user() -> String {
+    unsafe {
+        let pw = libc::getpwuid(libc::getuid());
+        if !pw.is_null() {
+            return CStr::from_ptr((*pw).pw_name).to_string_lossy().into_owned();
+        }
+    }
+    env::var("USER").unwrap_or(String::new())
+}
+
+fn hostname() -> String {
+    let mut buf = vec![0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    buf.truncate(len);
+    String::from_utf8_lossy(&buf).into_owned()
+}
+
+fn random_file(dir: &str, pattern: &str) -> String {
+    let dir = if dir.starts_with("~/") {
+        format!("{}{}", env::var("HOME").unwrap_or(String::new()), &dir[1..])
+    } else {
+        dir.to_string()
+    };
+    let mut files: Vec<String> = match fs::read_dir(&dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok())
+            .map(|e| e.file_name().to_string_lossy().into_owned())
+            // Like the shell, * does not match hidden files
+            .filter(|name| !name.starts_with('.') || pattern.starts_with('.'))
+            .filter(|name| matches(pattern.as_bytes(), name.as_bytes()))
+            .collect(),
+        Err(_) => Vec::new()
+    };
+    if files.is_empty() {
+        println!("Warning: no file matches {}{}", dir, pattern);
+        return String::new();
+    }
+    let i = (random() % files.len() as u64) as usize;
+    Path::new(&dir).join(files.swap_remove(i)).to_string_lossy().into_owned()
+}
+
+// Shell style matching with * and ?
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some((&b'*', rest)) => (0..name.len() + 1).any(|i| matches(rest, &name[i..])),
+        Some((&b'?', rest)) => !name.is_empty() && matches(rest, &name[1..]),
+        Some((&c, rest)) => name.first() == Some(&c) && matches(rest, &name[1..])
+    }
+}
+
+fn random() -> u64 {
+    let mut buf = [0u8; 8];
+    match File::open("/dev/urandom").and_then(|mut f| f.read_exact(&mut buf)) {
+        Ok(_) => u64::from_ne_bytes(buf),
+        // Still different every time
+        Err(_) => history::now()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::OsString;
+    use super::{Template, matches};
+
+    fn render(s: &str) -> String {
+        let mut vars = HashMap::new();
+        vars.insert(OsString::from("DISPLAY"), OsString::from(":1"));
+        Template::parse(s).unwrap().render("manual", &vars)
+    }
+
+    #[test]
+    fn literal_braces() {
+        assert_eq!(render("{{}}"), "{}");
+        assert_eq!(render("a{{{reason}}}b"), "a{manual}b");
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(render("--text={reason}"), "--text=manual");
+        assert_eq!(render("{env:DISPLAY}"), ":1");
+        assert_eq!(render("{env:NOT_SET}"), "");
+    }
+
+    #[test]
+    fn unclosed_braces() {
+        assert!(Template::parse("{reason").is_err());
+        assert!(Template::parse("{re{ason}").is_err());
+        assert!(Template::parse("reason}").is_err());
+    }
+
+    #[test]
+    fn unknown_placeholders() {
+        assert!(Template::parse("{nope}").is_err());
+        assert!(Template::parse("{reason:x}").is_err());
+        assert!(Template::parse("{env:}").is_err());
+        assert!(Template::parse("{random_file}").is_err());
+        assert!(Template::parse("{random_file:~/*/a.png}").is_err());
+    }
+
+    #[test]
+    fn glob() {
+        assert!(matches(b"*.png", b"a.png"));
+        assert!(matches(b"*.png", b".png"));
+        assert!(!matches(b"*.png", b"a.jpg"));
+        assert!(matches(b"a?c", b"abc"));
+        assert!(!matches(b"a?c", b"ac"));
+        assert!(matches(b"*", b""));
+        assert!(matches(b"a*b*c", b"axxbyyc"));
+        assert!(!matches(b"abc", b"abcd"));
+    }
+}
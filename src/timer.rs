@@ -0,0 +1,51 @@
+use libc;
+use std::collections::HashMap;
+use std::sync::mpsc::{Sender, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+use msg::{TimerMessage, CoreMessage, Timer};
+
+/// Time since boot, including time spent suspended. Deadlines use this, so "for 45 minutes" means wall clock
+/// minutes even if the machine sleeps in between.
+pub fn now() -> Duration {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_BOOTTIME, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// Tells core when a timer is due. Setting a timer that is already running moves it.
+pub fn actor_timer(core: Sender<CoreMessage>, inbox: Receiver<TimerMessage>) {
+    let mut timers: HashMap<Timer, Duration> = HashMap::new();
+    loop {
+        let next = timers.values().min().cloned();
+        // Waiting does not count time asleep, so core sends Resumed to make us look again
+        let message = match next {
+            Some(deadline) => {
+                let now = now();
+                if deadline <= now {
+                    Err(RecvTimeoutError::Timeout)
+                } else {
+                    inbox.recv_timeout(deadline - now)
+                }
+            },
+            None => inbox.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        };
+        match message {
+            Ok(TimerMessage::Set(timer, deadline)) => {
+                timers.insert(timer, deadline);
+            },
+            Ok(TimerMessage::Cancel(timer)) => {
+                timers.remove(&timer);
+            },
+            Ok(TimerMessage::Resumed) | Err(RecvTimeoutError::Timeout) => {
+                let now = now();
+                let due: Vec<Timer> = timers.iter().filter(|&(_, d)| *d <= now).map(|(t, _)| *t).collect();
+                for timer in due {
+                    timers.remove(&timer);
+                    core.send(CoreMessage::TimerExpired(timer)).unwrap();
+                }
+            },
+            Err(RecvTimeoutError::Disconnected) => return
+        }
+    }
+}